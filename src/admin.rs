@@ -0,0 +1,96 @@
+use std::{fs, path::Path};
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::config::{self, LightDefinition};
+
+pub const COMMAND_TOPIC: &str = "dmx/admin/set";
+pub const STATUS_TOPIC: &str = "dmx/admin/status";
+
+// Changes to the configured lights, sent as JSON to the admin topic:
+// {"action": "add", "light": {...}}, {"action": "edit", "light": {...}} or
+// {"action": "remove", "id": "..."}. The light is kept as sent so it can be
+// written to the config file the way the user wrote it.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum AdminCommand {
+    Add { light: serde_json::Value },
+    Edit { light: serde_json::Value },
+    Remove { id: String },
+}
+
+impl AdminCommand {
+    pub fn parse(payload: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(payload).map_err(|e| anyhow!("Invalid admin command: {}", e))
+    }
+
+    // The definition being added or edited
+    pub fn definition(&self) -> anyhow::Result<Option<LightDefinition>> {
+        match self {
+            AdminCommand::Add { light } | AdminCommand::Edit { light } => serde_json::from_value(light.clone())
+                .map(Some)
+                .map_err(|e| anyhow!("Invalid light definition: {}", e)),
+            AdminCommand::Remove { .. } => Ok(None),
+        }
+    }
+}
+
+// Replaces, appends or (without a light) removes the `[[lights]]` entry with
+// the given id, leaving comments and the rest of the file untouched
+pub fn persist(path: &Path, id: &str, light: Option<&serde_json::Value>) -> anyhow::Result<()> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to open the config file {}: {}", path.display(), e))?;
+    let mut document: DocumentMut = contents.parse()
+        .map_err(|e| anyhow!("Unable to parse the config file {}: {}", path.display(), e))?;
+
+    let table = light.map(to_table).transpose()?;
+    let lights = document.entry("lights")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| anyhow!("The lights in {} are not written as [[lights]] tables", path.display()))?;
+
+    let position = lights.iter().position(|light| light.get("id").and_then(|id| id.as_str()) == Some(id));
+    match (position, table) {
+        (Some(index), Some(table)) => *lights.get_mut(index).unwrap() = table,
+        (None, Some(table)) => lights.push(table),
+        (Some(index), None) => lights.remove(index),
+        (None, None) => return Err(anyhow!("Light {} is not in {}", id, path.display())),
+    }
+
+    config::replace_file(path, &document.to_string())
+        .map_err(|e| anyhow!("Unable to write the config file {}: {}", path.display(), e))
+}
+
+fn to_table(light: &serde_json::Value) -> anyhow::Result<Table> {
+    let contents = toml::to_string(&without_nulls(light))
+        .map_err(|e| anyhow!("Unable to convert light to TOML: {}", e))?;
+    let document: DocumentMut = contents.parse()?;
+    let mut table = document.as_table().clone();
+
+    // Written the way the example config has them: nested values as inline
+    // tables and the identifying keys first
+    for (mut key, item) in table.iter_mut() {
+        item.make_value();
+        key.fmt();
+    }
+    let rank = |key: &str| ["id", "display_name", "universe"].iter()
+        .position(|first| *first == key)
+        .unwrap_or(3);
+    table.sort_values_by(|a, _, b, _| rank(a.get()).cmp(&rank(b.get())));
+
+    Ok(table)
+}
+
+// TOML has no null, an omitted key means the same
+fn without_nulls(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => object.iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key.clone(), without_nulls(value)))
+            .collect(),
+        serde_json::Value::Array(array) => array.iter().map(without_nulls).collect(),
+        value => value.clone(),
+    }
+}
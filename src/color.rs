@@ -0,0 +1,259 @@
+// CIE 1931 XYZ tristimulus values
+pub type Xyz = [f32; 3];
+
+pub fn xy_to_xyz(x: f32, y: f32, luminance: f32) -> Xyz {
+    if y <= 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+    [x / y * luminance, luminance, (1.0 - x - y) / y * luminance]
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+pub fn srgb_to_xyz(r: u8, g: u8, b: u8) -> Xyz {
+    let (r, g, b) = (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b));
+    [
+        0.4124 * r + 0.3576 * g + 0.1805 * b,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        0.0193 * r + 0.1192 * g + 0.9505 * b,
+    ]
+}
+
+// Converts to the brightest sRGB color with the same chromaticity, clipping
+// anything outside the sRGB gamut
+pub fn xyz_to_srgb(xyz: Xyz) -> (u8, u8, u8) {
+    let [x, y, z] = xyz;
+    let linear = [
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    ];
+
+    let max = linear.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return (0, 0, 0);
+    }
+    (linear_to_srgb(linear[0] / max), linear_to_srgb(linear[1] / max), linear_to_srgb(linear[2] / max))
+}
+
+pub fn xy_to_srgb(x: f32, y: f32) -> (u8, u8, u8) {
+    xyz_to_srgb(xy_to_xyz(x, y, 1.0))
+}
+
+pub fn srgb_to_xy(r: u8, g: u8, b: u8) -> (f32, f32) {
+    let [x, y, z] = srgb_to_xyz(r, g, b);
+    let sum = x + y + z;
+    if sum <= 0.0 {
+        return (0.3127, 0.3290);
+    }
+    (x / sum, y / sum)
+}
+
+// Hue in degrees and saturation in percent, as Home Assistant sends them
+pub fn hs_to_srgb(h: f32, s: f32) -> (u8, u8, u8) {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let s = (s / 100.0).clamp(0.0, 1.0);
+
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    let channel = |value: f32| ((1.0 - s + s * value) * 255.0).round() as u8;
+    (channel(r), channel(g), channel(b))
+}
+
+// Chromaticity of a black body at the given color temperature (Kim et al.
+// cubic spline approximation, valid from 1667 K to 25000 K)
+pub fn mireds_to_xy(mireds: u16) -> (f32, f32) {
+    let t = (1_000_000.0 / mireds.max(1) as f32).clamp(1667.0, 25000.0);
+
+    let x = if t <= 4000.0 {
+        -0.2661239e9 / t.powi(3) - 0.2343589e6 / t.powi(2) + 0.8776956e3 / t + 0.179910
+    } else {
+        -3.0258469e9 / t.powi(3) + 2.107038e6 / t.powi(2) + 0.2226347e3 / t + 0.240390
+    };
+
+    let y = if t <= 2222.0 {
+        -1.1063814 * x.powi(3) - 1.3481102 * x.powi(2) + 2.1855583 * x - 0.20219683
+    } else if t <= 4000.0 {
+        -0.9549476 * x.powi(3) - 1.3741859 * x.powi(2) + 2.09137 * x - 0.16748867
+    } else {
+        3.081758 * x.powi(3) - 5.873387 * x.powi(2) + 3.7511299 * x - 0.37001483
+    };
+
+    (x, y)
+}
+
+pub fn mireds_to_srgb(mireds: u16) -> (u8, u8, u8) {
+    let (x, y) = mireds_to_xy(mireds);
+    xy_to_srgb(x, y)
+}
+
+fn solve3(m: [[f32; 3]; 3], v: [f32; 3]) -> Option<[f32; 3]> {
+    let det = |m: [[f32; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let d = det(m);
+    if d.abs() < 1e-9 {
+        return None;
+    }
+
+    // Cramer's rule
+    let mut result = [0.0; 3];
+    for (column, value) in result.iter_mut().enumerate() {
+        let mut replaced = m;
+        for row in 0..3 {
+            replaced[row][column] = v[row];
+        }
+        *value = det(replaced) / d;
+    }
+    Some(result)
+}
+
+const EPSILON: f32 = 1e-4;
+
+// Finds emitter levels (0.0-1.0) that reproduce the chromaticity of `target`
+// at the highest possible output. `emitters` are the XYZ values of each
+// emitter at full output. Returns None if the target is outside the gamut.
+//
+// This is a small linear program: maximise s subject to sum(a_i * E_i) = s * T
+// and 0 <= a_i <= 1. An optimum lies on a vertex where all but two emitters
+// sit at a bound, so with the handful of emitters a fixture has we can simply
+// try every vertex.
+pub fn mix(target: Xyz, emitters: &[Xyz]) -> Option<Vec<f32>> {
+    if target[1] <= 0.0 {
+        return Some(vec![0.0; emitters.len()]);
+    }
+    let target = [target[0] / target[1], 1.0, target[2] / target[1]];
+
+    let n = emitters.len();
+    let mut best: Option<(f32, Vec<f32>)> = None;
+
+    for i in 0..n {
+        for j in (i + 1)..n {
+            let others: Vec<usize> = (0..n).filter(|&k| k != i && k != j).collect();
+
+            for mask in 0..(1u32 << others.len()) {
+                let mut levels = vec![0.0; n];
+                let mut fixed = [0.0; 3];
+                for (bit, &k) in others.iter().enumerate() {
+                    if mask >> bit & 1 == 1 {
+                        levels[k] = 1.0;
+                        for c in 0..3 {
+                            fixed[c] += emitters[k][c];
+                        }
+                    }
+                }
+
+                let m = [
+                    [emitters[i][0], emitters[j][0], -target[0]],
+                    [emitters[i][1], emitters[j][1], -target[1]],
+                    [emitters[i][2], emitters[j][2], -target[2]],
+                ];
+                let Some([a, b, s]) = solve3(m, [-fixed[0], -fixed[1], -fixed[2]]) else { continue };
+
+                let in_range = |level: f32| (-EPSILON..=1.0 + EPSILON).contains(&level);
+                if !in_range(a) || !in_range(b) || s <= EPSILON {
+                    continue;
+                }
+
+                if best.as_ref().is_none_or(|(best_s, _)| s > *best_s + EPSILON) {
+                    levels[i] = a.clamp(0.0, 1.0);
+                    levels[j] = b.clamp(0.0, 1.0);
+                    best = Some((s, levels));
+                }
+            }
+        }
+    }
+
+    best.map(|(_, levels)| levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance
+    }
+
+    #[test]
+    fn converts_hue_and_saturation() {
+        assert_eq!(hs_to_srgb(0.0, 100.0), (255, 0, 0));
+        assert_eq!(hs_to_srgb(120.0, 100.0), (0, 255, 0));
+        assert_eq!(hs_to_srgb(240.0, 100.0), (0, 0, 255));
+        assert_eq!(hs_to_srgb(60.0, 50.0), (255, 255, 128));
+        // Hue wraps around and saturation is clamped
+        assert_eq!(hs_to_srgb(360.0, 100.0), (255, 0, 0));
+        assert_eq!(hs_to_srgb(-120.0, 200.0), (0, 0, 255));
+        assert_eq!(hs_to_srgb(200.0, 0.0), (255, 255, 255));
+    }
+
+    #[test]
+    fn follows_the_black_body_locus() {
+        // Standard illuminant A (2856 K) and D65 (6504 K, slightly off the locus)
+        let (x, y) = mireds_to_xy(350);
+        assert!(close(x, 0.4476, 0.002) && close(y, 0.4074, 0.002), "{} {}", x, y);
+        let (x, y) = mireds_to_xy(154);
+        assert!(close(x, 0.3127, 0.005) && close(y, 0.3290, 0.01), "{} {}", x, y);
+
+        // Temperatures outside the approximation are clamped, 0 does not divide by zero
+        assert_eq!(mireds_to_xy(1000), mireds_to_xy(600));
+        assert_eq!(mireds_to_xy(0), mireds_to_xy(40));
+    }
+
+    #[test]
+    fn mixes_inside_the_gamut_at_full_output() {
+        let emitters = [srgb_to_xyz(255, 0, 0), srgb_to_xyz(0, 255, 0), srgb_to_xyz(0, 0, 255)];
+
+        let levels = mix(srgb_to_xyz(255, 255, 255), &emitters).unwrap();
+        assert!(levels.iter().all(|level| close(*level, 1.0, 0.01)), "{:?}", levels);
+
+        // Brightness of the target does not matter, only its chromaticity
+        let levels = mix(srgb_to_xyz(64, 0, 0), &emitters).unwrap();
+        assert!(close(levels[0], 1.0, 0.01) && levels[1] < 0.01 && levels[2] < 0.01, "{:?}", levels);
+    }
+
+    #[test]
+    fn mixes_extra_emitters_for_brighter_output() {
+        let rgb = [srgb_to_xyz(255, 0, 0), srgb_to_xyz(0, 255, 0), srgb_to_xyz(0, 0, 255)];
+        let mut rgbw = rgb.to_vec();
+        rgbw.push(srgb_to_xyz(255, 255, 255));
+
+        // White is reached with the white emitter alone or all of them at once
+        let levels = mix(srgb_to_xyz(255, 255, 255), &rgbw).unwrap();
+        assert!(levels.iter().all(|level| close(*level, 1.0, 0.01)), "{:?}", levels);
+    }
+
+    #[test]
+    fn rejects_colors_outside_the_gamut() {
+        let red_green = [srgb_to_xyz(255, 0, 0), srgb_to_xyz(0, 255, 0)];
+        assert_eq!(mix(srgb_to_xyz(0, 0, 255), &red_green), None);
+        assert_eq!(mix([0.0, 0.0, 0.0], &red_green), Some(vec![0.0, 0.0]));
+    }
+}
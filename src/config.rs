@@ -1,34 +1,588 @@
+mod legacy;
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use toml_edit::DocumentMut;
+use crate::fixture::{AxisLimits, Calibration, ChannelDefinition, ChannelRole, Fixture, FixtureProfile, ProfileSource};
+use crate::hass::ColorMode;
 use crate::light::{RGBDimmerMapping, RGBWDimmerMapping};
+use crate::patch::{self, PatchEntry, PatchTable};
 
 #[derive(Deserialize,Debug)]
 pub struct Config {
     pub mqtt: MQTTConfig,
-    pub lights: Vec<LightSpecification>,
+    pub universes: Vec<UniverseSpecification>,
+    pub lights: Vec<LightDefinition>,
+    pub profile_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileSource>,
+    #[serde(default)]
+    pub patch: Vec<PatchEntry>,
+    #[serde(default)]
+    pub groups: Vec<GroupDefinition>,
+    #[serde(default)]
+    pub zones: Vec<ZoneDefinition>,
 }
 
-#[derive(Deserialize,Debug)]
+impl Config {
+    // Loads the config, along with the ids of lights still written in the old
+    // format. Those are migrated in memory, see `migrate_file` to update the file.
+    pub fn load(path: &Path) -> anyhow::Result<(Config, Vec<String>)> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError::new(path, format!("Unable to open the config file: {}", e)))?;
+
+        let (mut config, migrated) = match parse_toml::<Config>(path, &contents) {
+            Ok(config) => (config, Vec::new()),
+            Err(error) => {
+                // Errors are reported against the file as written, even if
+                // migrating it doesn't help
+                let Ok(mut document) = contents.parse::<DocumentMut>() else { return Err(error.into()) };
+                match legacy::migrate(&mut document) {
+                    Ok(migrated) if !migrated.is_empty() => {
+                        let config: Config = toml::from_str(&document.to_string()).map_err(|_| error)?;
+                        (config, migrated)
+                    }
+                    Ok(_) => return Err(error.into()),
+                    Err(e) => return Err(anyhow!("{}\n{}", error, e)),
+                }
+            }
+        };
+
+        // Relative paths are taken from the config's directory rather than
+        // wherever the bridge was started
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if let Some(profile_dir) = config.profile_dir.as_mut() {
+            *profile_dir = dir.join(&*profile_dir);
+        }
+        for source in config.profiles.values_mut() {
+            source.relative_to(dir);
+        }
+
+        Ok((config, migrated))
+    }
+
+    // Rewrites lights in the old format in the config file, returning their ids
+    pub fn migrate_file(path: &Path) -> anyhow::Result<Vec<String>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError::new(path, format!("Unable to open the config file: {}", e)))?;
+        let mut document: DocumentMut = contents.parse()
+            .map_err(|e: toml_edit::TomlError| ConfigError::at(path, &contents, e.span().map(|span| span.start), e.message()))?;
+
+        let migrated = legacy::migrate(&mut document)?;
+        if !migrated.is_empty() {
+            replace_file(path, &document.to_string())
+                .map_err(|e| ConfigError::new(path, format!("Unable to write the config file: {}", e)))?;
+        }
+        Ok(migrated)
+    }
+
+    // Every profile, checked for channel map problems which are reported together,
+    // along with what imported profiles leave undriven
+    pub fn fixture_profiles(&self) -> anyhow::Result<(HashMap<String, FixtureProfile>, Vec<String>)> {
+        let mut profiles = match &self.profile_dir {
+            Some(dir) => FixtureProfile::load_dir(dir)?,
+            None => HashMap::new(),
+        };
+
+        let mut problems = Vec::new();
+        let mut unsupported = Vec::new();
+        for (name, source) in self.profiles.iter() {
+            match source.load() {
+                Ok((profile, dropped)) => {
+                    unsupported.extend(dropped.into_iter().map(|dropped| format!("Profile {}: {}", name, dropped)));
+                    profiles.insert(name.clone(), profile);
+                }
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+        unsupported.sort();
+
+        let mut names: Vec<&String> = profiles.keys().collect();
+        names.sort();
+        for name in names {
+            problems.extend(profiles[name].problems().into_iter().map(|problem| format!("Profile {}: {}", name, problem)));
+        }
+
+        if !problems.is_empty() {
+            return Err(anyhow!("Found {} problem(s) with the profiles:\n  {}", problems.len(), problems.join("\n  ")));
+        }
+        Ok((profiles, unsupported))
+    }
+
+    // The patch, checked against the configured universes with all problems reported together
+    pub fn patch_table(&self) -> anyhow::Result<PatchTable> {
+        let universes: Vec<&str> = self.universes.iter().map(|universe| universe.id.as_str()).collect();
+        let problems = patch::problems(&self.patch, &universes);
+
+        if !problems.is_empty() {
+            return Err(anyhow!("Found {} problem(s) with the patch:\n  {}", problems.len(), problems.join("\n  ")));
+        }
+        Ok(PatchTable::new(&self.patch))
+    }
+
+    pub fn light_specifications(&self) -> anyhow::Result<Vec<LightSpecification>> {
+        resolve_lights(&self.lights, &self.fixture_profiles()?.0, &self.universes, &self.patch_table()?)
+    }
+
+    // Groups, followed by zones which are controlled the same way
+    pub fn group_specifications(&self, lights: &[LightSpecification]) -> anyhow::Result<Vec<GroupSpecification>> {
+        let resolved = self.groups.iter()
+            .map(|group| group.resolve(lights))
+            .chain(self.zones.iter().map(|zone| zone.resolve(lights)));
+
+        let mut problems = Vec::new();
+        let mut groups: Vec<GroupSpecification> = Vec::new();
+        for group in resolved {
+            match group {
+                Ok(group) if groups.iter().any(|other| other.id == group.id) => {
+                    problems.push(format!("Group {}: id is already used by another group or zone", group.id));
+                }
+                Ok(group) => groups.push(group),
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(anyhow!("Found {} problem(s) with the groups:\n  {}", problems.len(), problems.join("\n  ")));
+        }
+        Ok(groups)
+    }
+}
+
+// Resolves every light and checks the patch as a whole: ids must be unique,
+// universes configured, and each channel inside the universe and used by a
+// single light once the soft patch has moved it. All problems are reported
+// together.
+pub fn resolve_lights(
+    lights: &[LightDefinition],
+    profiles: &HashMap<String, FixtureProfile>,
+    universes: &[UniverseSpecification],
+    patch: &PatchTable,
+) -> anyhow::Result<Vec<LightSpecification>> {
+    let mut problems = Vec::new();
+    let mut specifications: Vec<LightSpecification> = Vec::new();
+    let mut claimed: HashMap<(&str, u16), &str> = HashMap::new();
+
+    for light in lights.iter() {
+        if !universes.iter().any(|universe| universe.id == light.universe) {
+            problems.push(format!("Light {}: universe {} is not configured", light.id, light.universe));
+        }
+
+        let resolved = match light.resolve(profiles) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                problems.push(e.to_string());
+                continue;
+            }
+        };
+
+        for specification in resolved.iter() {
+            if let Some(other) = specifications.iter().find(|other| other.id == specification.id) {
+                problems.push(format!("Light {}: id {} is already used by {}", light.id, specification.id, describe(other)));
+            }
+        }
+
+        // Slots are 0-based in a mapping, and shown as 1-based DMX addresses
+        // when the light is patched by its start address
+        let (field, shown) = match light.address {
+            Some(address) => (format!("address {}", address), 1),
+            None => ("mapping".to_string(), 0),
+        };
+        // Wide enough for any offset a profile can give, so those are
+        // reported rather than overflowing
+        let fixture = &resolved[0].fixture;
+        let mut slots: Vec<u32> = fixture.profile.channels.iter()
+            .map(|channel| fixture.base as u32 + channel.offset as u32)
+            .collect();
+        slots.sort();
+        let mut reused: Vec<u32> = slots.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+        reused.dedup();
+        if !reused.is_empty() {
+            problems.push(format!("Light {}: {} uses {} for more than one channel", light.id, field, format_slots(&reused, shown)));
+        }
+        slots.dedup();
+
+        let outside: Vec<u32> = slots.iter().copied().filter(|slot| *slot >= 512).collect();
+        if !outside.is_empty() {
+            problems.push(format!("Light {}: {} puts channels at {}, outside {}-{}",
+                light.id, field, format_slots(&outside, shown), shown, 511 + shown));
+        }
+
+        // Claims are on the physical channels, shown as such
+        let mut overlaps: BTreeMap<(&str, &str), Vec<u32>> = BTreeMap::new();
+        let mut patched = false;
+        for slot in slots.iter().filter(|slot| **slot < 512).map(|slot| *slot as u16) {
+            let (universe, target) = patch.target(&light.universe, slot);
+            match claimed.entry((universe, target)) {
+                Entry::Occupied(entry) => {
+                    overlaps.entry((*entry.get(), universe)).or_default().push(target as u32);
+                    patched |= (universe, target) != (light.universe.as_str(), slot);
+                }
+                Entry::Vacant(entry) => { entry.insert(light.id.as_str()); }
+            }
+        }
+        for ((other, universe), mut shared) in overlaps {
+            shared.sort();
+            problems.push(format!("Light {}: {} overlaps light {} on universe {} at {}{}",
+                light.id, field, other, universe, format_slots(&shared, shown), if patched { " once patched" } else { "" }));
+        }
+
+        specifications.extend(resolved);
+    }
+
+    if !problems.is_empty() {
+        return Err(anyhow!("Found {} problem(s) with the lights:\n  {}", problems.len(), problems.join("\n  ")));
+    }
+    Ok(specifications)
+}
+
+fn describe(light: &LightSpecification) -> String {
+    match &light.parent {
+        Some(parent) => format!("a segment of light {}", parent),
+        None => "another light".to_string(),
+    }
+}
+
+// Sorted slots as runs, e.g. "8-11, 14", shifted by `shown`
+fn format_slots(slots: &[u32], shown: u32) -> String {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for slot in slots.iter().map(|slot| slot + shown) {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == slot => *last = slot,
+            _ => runs.push((slot, slot)),
+        }
+    }
+    runs.iter()
+        .map(|(first, last)| if first == last { first.to_string() } else { format!("{}-{}", first, last) })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// A config or profile file that could not be read, with the line and column
+// of the problem where known
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(path: &Path, message: impl Into<String>) -> Self {
+        ConfigError { path: path.to_path_buf(), position: None, message: message.into() }
+    }
+
+    // Points at a byte offset into the file's contents
+    pub fn at(path: &Path, contents: &str, offset: Option<usize>, message: impl Into<String>) -> Self {
+        let position = offset.map(|offset| {
+            let before = &contents[..offset.min(contents.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+            (line, column)
+        });
+        ConfigError { position, ..ConfigError::new(path, message) }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{}:{}: {}", self.path.display(), line, column, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Writes next to the file and swaps it in, so a failed write can't leave it truncated
+pub fn replace_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let temporary = path.with_extension("toml.tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+pub fn parse_toml<T: DeserializeOwned>(path: &Path, contents: &str) -> Result<T, ConfigError> {
+    toml::from_str(contents)
+        .map_err(|e| ConfigError::at(path, contents, e.span().map(|span| span.start), e.message()))
+}
+
+#[derive(Deserialize,Debug,PartialEq)]
 pub struct MQTTConfig {
     pub host: String,
     pub username: Option<String>,
     pub password: Option<String>,
+    // Accept light changes published to the admin topic
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl MQTTConfig {
+    // `host` may be a bare host name or address, or a full URI like "ssl://broker:8883"
+    pub fn server_uri(&self) -> String {
+        match self.host.contains("://") {
+            true => self.host.clone(),
+            false if self.host.contains(':') => format!("tcp://{}", self.host),
+            false => format!("tcp://{}:1883", self.host),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UniverseSpecification {
+    pub id: String,
+    pub serial: String,
+}
+
+// A light as written in the config file. It is either patched with an absolute
+// `mapping` of 0-based frame slots, or with a `profile` of channel offsets and
+// the 1-based DMX start `address` shown on the fixture.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LightDefinition {
+    pub universe: String,
+    pub id: String,
+    pub display_name: String,
+    pub mapping: Option<LightChannelMapping>,
+    pub profile: Option<String>,
+    pub address: Option<u16>,
+    pub calibration: Option<Calibration>,
+    pub pan: Option<AxisLimits>,
+    pub tilt: Option<AxisLimits>,
+    // Expose every cell of a pixel fixture as a light of its own
+    #[serde(default)]
+    pub segments: bool,
+    pub position: Option<Position>,
+    pub orientation: Option<Orientation>,
+}
+
+// Where a fixture hangs, in meters. 2D layouts leave out `z`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub z: f32,
+}
+
+// Which way a fixture points, in degrees
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Orientation {
+    #[serde(default)]
+    pub yaw: f32,
+    #[serde(default)]
+    pub pitch: f32,
+    #[serde(default)]
+    pub roll: f32,
+}
+
+// A named area of the venue, made of the listed lights and every light
+// positioned within `bounds`
+#[derive(Deserialize, Debug, Clone)]
+pub struct ZoneDefinition {
+    pub id: String,
+    pub display_name: String,
+    #[serde(default)]
+    pub lights: Vec<String>,
+    pub bounds: Option<(Position, Position)>,
+}
+
+impl ZoneDefinition {
+    fn contains(&self, light: &LightSpecification) -> bool {
+        if self.lights.contains(&light.id) {
+            return true;
+        }
+        // Segments share their fixture's position and are reached through it
+        let (Some((min, max)), Some(position), None) = (&self.bounds, &light.position, &light.parent) else {
+            return false;
+        };
+        (min.x..=max.x).contains(&position.x)
+            && (min.y..=max.y).contains(&position.y)
+            && (min.z..=max.z).contains(&position.z)
+    }
+
+    pub fn resolve(&self, lights: &[LightSpecification]) -> anyhow::Result<GroupSpecification> {
+        if let Some(unknown) = self.lights.iter().find(|id| !lights.iter().any(|light| &light.id == *id)) {
+            return Err(anyhow!("Zone {} has unknown light {}", self.id, unknown));
+        }
+
+        let members: Vec<String> = lights.iter()
+            .filter(|light| self.contains(light))
+            .map(|light| light.id.clone())
+            .collect();
+        if members.is_empty() {
+            return Err(anyhow!("Zone {} contains no lights", self.id));
+        }
+
+        GroupDefinition { id: self.id.clone(), display_name: self.display_name.clone(), members }.resolve(lights)
+    }
+}
+
+impl LightDefinition {
+    // Resolves the light, followed by a light per segment if requested
+    pub fn resolve(&self, profiles: &HashMap<String, FixtureProfile>) -> anyhow::Result<Vec<LightSpecification>> {
+        let fixture = match (&self.mapping, &self.profile, self.address) {
+            (Some(mapping), None, None) => Fixture::new(mapping.to_profile(), 0),
+            (None, Some(profile), Some(address)) => {
+                let profile = profiles.get(profile)
+                    .ok_or_else(|| anyhow!("Light {} uses unknown profile {}", self.id, profile))?;
+                if !(1..=512).contains(&address) {
+                    return Err(anyhow!("Light {} has start address {}, expected 1-512", self.id, address));
+                }
+                Fixture::new(profile.clone(), address - 1)
+            }
+            (None, Some(_), None) => return Err(anyhow!("Light {} uses a profile but has no start address", self.id)),
+            _ => return Err(anyhow!("Light {} must have either a mapping or a profile and address", self.id)),
+        };
+
+        if let Some(white_gain) = self.calibration.as_ref().and_then(|calibration| calibration.white_gain)
+            && white_gain <= 0.0 {
+            return Err(anyhow!("Light {} has white_gain {}, expected above 0", self.id, white_gain));
+        }
+        let mut fixture = match &self.calibration {
+            Some(calibration) => fixture.with_calibration(calibration.clone()),
+            None => fixture,
+        };
+
+        for (axis, limits) in [("pan", &self.pan), ("tilt", &self.tilt)] {
+            let Some(limits) = limits else { continue };
+            if limits.min > limits.max {
+                return Err(anyhow!("Light {} has {} limits {}-{}, min must not exceed max", self.id, axis, limits.min, limits.max));
+            }
+        }
+        fixture.pan = self.pan.clone().unwrap_or_default();
+        fixture.tilt = self.tilt.clone().unwrap_or_default();
+
+        if self.segments && fixture.profile.cell_count() == 0 {
+            return Err(anyhow!("Light {} has segments enabled but its profile has no cells", self.id));
+        }
+
+        let segments = (0..fixture.profile.cell_count())
+            .filter(|_| self.segments)
+            .map(|cell| LightSpecification {
+                universe: self.universe.clone(),
+                id: format!("{}_{}", self.id, cell + 1),
+                display_name: format!("{} {}", self.display_name, cell + 1),
+                fixture: Fixture { profile: fixture.profile.cell(cell).unwrap(), ..fixture.clone() },
+                parent: Some(self.id.clone()),
+                position: self.position,
+                orientation: self.orientation,
+            })
+            .collect::<Vec<_>>();
+
+        let mut specifications = vec![LightSpecification {
+            universe: self.universe.clone(),
+            id: self.id.clone(),
+            display_name: self.display_name.clone(),
+            fixture: Fixture { profile: fixture.profile.flattened(), ..fixture },
+            parent: None,
+            position: self.position,
+            orientation: self.orientation,
+        }];
+        specifications.extend(segments);
+
+        Ok(specifications)
+    }
 }
 
+// Lights that Home Assistant sees and controls as a single light
 #[derive(Deserialize, Debug, Clone)]
+pub struct GroupDefinition {
+    pub id: String,
+    pub display_name: String,
+    pub members: Vec<String>,
+}
+
+impl GroupDefinition {
+    pub fn resolve(&self, lights: &[LightSpecification]) -> anyhow::Result<GroupSpecification> {
+        if self.members.is_empty() {
+            return Err(anyhow!("Group {} has no members", self.id));
+        }
+        if lights.iter().any(|light| light.id == self.id) {
+            return Err(anyhow!("Group {} has the same id as a light", self.id));
+        }
+
+        let members = self.members.iter()
+            .map(|member| lights.iter()
+                .find(|light| &light.id == member)
+                .cloned()
+                .ok_or_else(|| anyhow!("Group {} has unknown member {}", self.id, member)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(GroupSpecification {
+            id: self.id.clone(),
+            display_name: self.display_name.clone(),
+            members,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupSpecification {
+    pub id: String,
+    pub display_name: String,
+    pub members: Vec<LightSpecification>,
+}
+
+impl GroupSpecification {
+    // Modes every member supports, so that any command works on all of them
+    pub fn supported_color_modes(&self) -> Vec<ColorMode> {
+        let mut modes = self.members[0].supported_color_modes();
+        for member in self.members.iter().skip(1) {
+            let supported = member.supported_color_modes();
+            modes.retain(|mode| supported.contains(mode));
+        }
+
+        if !modes.is_empty() {
+            modes
+        } else if self.members.iter().any(|member| member.color_mode() == ColorMode::OnOff) {
+            vec![ColorMode::OnOff]
+        } else {
+            vec![ColorMode::Brightness]
+        }
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.supported_color_modes()[0].clone()
+    }
+
+    // Color temperatures within reach of every member
+    pub fn min_mireds(&self) -> u16 {
+        self.members.iter().map(|member| member.fixture.profile.min_mireds()).max().unwrap()
+    }
+
+    pub fn max_mireds(&self) -> u16 {
+        let max = self.members.iter().map(|member| member.fixture.profile.max_mireds()).min().unwrap();
+        max.max(self.min_mireds())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LightSpecification {
     pub universe: String,
     pub id: String,
     pub display_name: String,
-    pub mapping: LightChannelMapping,
+    pub fixture: Fixture,
+    // Whole fixture a segment light belongs to
+    pub parent: Option<String>,
+    pub position: Option<Position>,
+    pub orientation: Option<Orientation>,
 }
 
 
 impl LightSpecification {
-    pub fn color_mode(&self) -> Option<String> {
-        match &self.mapping {
-            LightChannelMapping::RGBWDimmer(_) => Some("rgbw".to_string()),
-            LightChannelMapping::RGBDimmer(_) => Some("rgb".to_string()),
-        }
+    pub fn color_mode(&self) -> ColorMode {
+        self.fixture.profile.color_mode()
+    }
+
+    pub fn supported_color_modes(&self) -> Vec<ColorMode> {
+        self.fixture.profile.supported_color_modes()
     }
 }
 
@@ -37,28 +591,307 @@ impl LightSpecification {
 #[derive(Deserialize,Debug,Clone)]
 #[serde(tag = "type")]
 pub enum LightChannelMapping {
-    // RGBW(RGBWMapping),
+    RGBW(RGBWMapping),
     RGBWDimmer(RGBWDimmerMapping),
-    // RGB(RGBMapping),
+    RGB(RGBMapping),
     RGBDimmer(RGBDimmerMapping),
+    RGBWWDimmer(RGBWWDimmerMapping),
+    Dimmer(DimmerMapping),
+    Relay(RelayMapping),
+}
+
+// Fixtures without a master dimmer, brightness is applied to the color channels
+#[derive(Deserialize,Debug,Clone)]
+pub struct RGBWMapping {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    pub w: u16,
+}
+
+#[derive(Deserialize,Debug,Clone)]
+pub struct RGBMapping {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+#[derive(Deserialize,Debug,Clone)]
+pub struct RGBWWDimmerMapping {
+    pub dimmer: u16,
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+    // Cold and warm white
+    pub c: u16,
+    pub w: u16,
+}
+
+#[derive(Deserialize,Debug,Clone)]
+pub struct DimmerMapping {
+    pub dimmer: u16,
+}
+
+#[derive(Deserialize,Debug,Clone)]
+pub struct RelayMapping {
+    pub channel: u16,
+
+    // Value written while switched on. Dimmer packs in switch mode close the
+    // relay once the channel passes their threshold, which is not always 255.
+    #[serde(default = "default_relay_threshold")]
+    pub threshold: u8,
+}
+
+fn default_relay_threshold() -> u8 {
+    255
 }
 
 impl LightChannelMapping {
-    pub fn off_frame_values(&self) -> Vec<(u16, u8)> {
-        match self {
+    // Converts the shorthand mapping into an equivalent fixture profile
+    pub fn to_profile(&self) -> FixtureProfile {
+        let channels = match self {
+            LightChannelMapping::RGBW(mapping) => vec![
+                ChannelDefinition::new(ChannelRole::Red, mapping.r),
+                ChannelDefinition::new(ChannelRole::Green, mapping.g),
+                ChannelDefinition::new(ChannelRole::Blue, mapping.b),
+                ChannelDefinition::new(ChannelRole::White, mapping.w),
+            ],
+            LightChannelMapping::RGB(mapping) => vec![
+                ChannelDefinition::new(ChannelRole::Red, mapping.r),
+                ChannelDefinition::new(ChannelRole::Green, mapping.g),
+                ChannelDefinition::new(ChannelRole::Blue, mapping.b),
+            ],
             LightChannelMapping::RGBWDimmer(mapping) => vec![
-                (mapping.r, 0),
-                (mapping.g, 0),
-                (mapping.b, 0),
-                (mapping.w, 0),
-                (mapping.dimmer, 0),
+                ChannelDefinition::new(ChannelRole::Intensity, mapping.dimmer),
+                ChannelDefinition::new(ChannelRole::Red, mapping.r),
+                ChannelDefinition::new(ChannelRole::Green, mapping.g),
+                ChannelDefinition::new(ChannelRole::Blue, mapping.b),
+                ChannelDefinition::new(ChannelRole::White, mapping.w),
             ],
             LightChannelMapping::RGBDimmer(mapping) => vec![
-                (mapping.r, 0),
-                (mapping.g, 0),
-                (mapping.b, 0),
-                (mapping.dimmer, 0),
+                ChannelDefinition::new(ChannelRole::Intensity, mapping.dimmer),
+                ChannelDefinition::new(ChannelRole::Red, mapping.r),
+                ChannelDefinition::new(ChannelRole::Green, mapping.g),
+                ChannelDefinition::new(ChannelRole::Blue, mapping.b),
+            ],
+            LightChannelMapping::RGBWWDimmer(mapping) => vec![
+                ChannelDefinition::new(ChannelRole::Intensity, mapping.dimmer),
+                ChannelDefinition::new(ChannelRole::Red, mapping.r),
+                ChannelDefinition::new(ChannelRole::Green, mapping.g),
+                ChannelDefinition::new(ChannelRole::Blue, mapping.b),
+                ChannelDefinition::new(ChannelRole::CoolWhite, mapping.c),
+                ChannelDefinition::new(ChannelRole::WarmWhite, mapping.w),
             ],
+            LightChannelMapping::Dimmer(mapping) => vec![
+                ChannelDefinition::new(ChannelRole::Intensity, mapping.dimmer),
+            ],
+            LightChannelMapping::Relay(mapping) => vec![ChannelDefinition {
+                range: (0, mapping.threshold),
+                ..ChannelDefinition::new(ChannelRole::Switch, mapping.channel)
+            }],
+        };
+
+        FixtureProfile::new(channels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        let toml = format!("mqtt = {{ host = \"mqtt://localhost:1883\" }}\nuniverses = [{{ id = \"dmx1\", serial = \"A\" }}]\n{}", toml);
+        toml::from_str(&toml).unwrap()
+    }
+
+    fn profiles() -> HashMap<String, FixtureProfile> {
+        let par = FixtureProfile::new(vec![
+            ChannelDefinition::new(ChannelRole::Intensity, 0),
+            ChannelDefinition::new(ChannelRole::Red, 1),
+            ChannelDefinition::new(ChannelRole::Green, 2),
+            ChannelDefinition::new(ChannelRole::Blue, 3),
+        ]);
+        HashMap::from([("par".to_string(), par)])
+    }
+
+    fn problems(toml: &str) -> Vec<String> {
+        let config = config(toml);
+        match resolve_lights(&config.lights, &profiles(), &config.universes, &config.patch_table().unwrap()) {
+            Ok(_) => Vec::new(),
+            Err(e) => e.to_string().lines().skip(1).map(|line| line.trim().to_string()).collect(),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn points_errors_at_line_and_column() {
+        let path = Path::new("config.toml");
+        let contents = "[mqtt]\nhost = 1\n";
+
+        let error = ConfigError::at(path, contents, Some(0), "first");
+        assert_eq!(error.to_string(), "config.toml:1:1: first");
+        let error = ConfigError::at(path, contents, contents.find('1'), "second");
+        assert_eq!(error.to_string(), "config.toml:2:8: second");
+        // Offsets past the end point just after the last character
+        let error = ConfigError::at(path, contents, Some(100), "past");
+        assert_eq!(error.position, Some((3, 1)));
+        let error = ConfigError::at(path, contents, None, "nowhere");
+        assert_eq!(error.to_string(), "config.toml: nowhere");
+    }
+
+    #[test]
+    fn counts_columns_in_characters() {
+        let contents = "name = \"Bühne\" x";
+        let error = ConfigError::at(Path::new("c.toml"), contents, contents.find('x'), "here");
+        assert_eq!(error.position, Some((1, 16)));
+    }
+
+    #[test]
+    fn reports_toml_errors_with_their_position() {
+        let contents = "[mqtt]\nhost = \"broker\"\n\n[[universes]]\nid = 1\n";
+        let error = parse_toml::<Config>(Path::new("config.toml"), contents).unwrap_err();
+        assert_eq!(error.position.map(|(line, _)| line), Some(5), "{}", error);
+    }
+
+    #[test]
+    fn loads_the_example() {
+        let (config, migrated) = Config::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("config.example.toml")).unwrap();
+        assert!(migrated.is_empty());
+
+        let lights = config.light_specifications().unwrap();
+        config.group_specifications(&lights).unwrap();
+        config.patch_table().unwrap();
+    }
+
+    #[test]
+    fn reports_profile_errors_as_themselves() {
+        let path = Path::new("config.toml");
+        let contents = "[mqtt]\nhost = \"broker\"\n\n[profiles.par]\nchannels = [{ role = \"bogus\", offset = 0 }]\n";
+        let error = parse_toml::<Config>(path, contents).unwrap_err();
+        // The position is that of the profile the mistake is in
+        assert!(error.message.contains("unknown variant `bogus`"), "{}", error);
+        assert_eq!(error.position, Some((4, 1)), "{}", error);
+
+        let contents = "[mqtt]\nhost = \"broker\"\n\n[profiles.par]\nfile = \"par.toml\"\nofl = \"par.json\"\n";
+        let error = parse_toml::<Config>(path, contents).unwrap_err();
+        assert!(error.message.contains("only one of `file`, `ofl`"), "{}", error);
+
+        let contents = "[mqtt]\nhost = \"broker\"\n\n[profiles.par]\nofl = \"par.json\"\nmdoe = \"8ch\"\n";
+        let error = parse_toml::<Config>(path, contents).unwrap_err();
+        assert!(error.message.contains("mdoe"), "{}", error);
+    }
+
+    #[test]
+    fn connects_to_the_configured_broker() {
+        let mqtt = |host: &str| MQTTConfig { host: host.to_string(), username: None, password: None, admin: false };
+        assert_eq!(mqtt("192.168.0.33").server_uri(), "tcp://192.168.0.33:1883");
+        assert_eq!(mqtt("broker:1884").server_uri(), "tcp://broker:1884");
+        assert_eq!(mqtt("ssl://broker:8883").server_uri(), "ssl://broker:8883");
+    }
+
+    #[test]
+    fn resolves_mapped_and_addressed_lights() {
+        let config = config(r#"
+            [[lights]]
+            id = "strip"
+            display_name = "Strip"
+            universe = "dmx1"
+            mapping = { type = "RGB", r = 0, g = 1, b = 2 }
+
+            [[lights]]
+            id = "par"
+            display_name = "Par"
+            universe = "dmx1"
+            profile = "par"
+            address = 4
+        "#);
+        let lights = resolve_lights(&config.lights, &profiles(), &config.universes, &config.patch_table().unwrap()).unwrap();
+
+        let par = &lights[1].fixture;
+        assert_eq!(par.profile.channels.iter().map(|channel| par.address(channel).unwrap()).collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn reports_every_problem_together() {
+        let problems = problems(r#"
+            [[lights]]
+            id = "strip"
+            display_name = "Strip"
+            universe = "dmx1"
+            mapping = { type = "RGB", r = 0, g = 1, b = 2 }
+
+            [[lights]]
+            id = "par"
+            display_name = "Par"
+            universe = "dmx1"
+            profile = "par"
+            address = 3
+
+            [[lights]]
+            id = "par"
+            display_name = "Other par"
+            universe = "dmx2"
+            profile = "par"
+            address = 511
+        "#);
+
+        assert_eq!(problems, vec![
+            "Light par: address 3 overlaps light strip on universe dmx1 at 3",
+            "Light par: universe dmx2 is not configured",
+            "Light par: id par is already used by another light",
+            "Light par: address 511 puts channels at 513-514, outside 1-512",
+        ]);
+    }
+
+    #[test]
+    fn checks_overlaps_after_the_patch() {
+        let problems = problems(r#"
+            [[lights]]
+            id = "a"
+            display_name = "A"
+            universe = "dmx1"
+            mapping = { type = "RGB", r = 0, g = 1, b = 2 }
+
+            [[lights]]
+            id = "b"
+            display_name = "B"
+            universe = "dmx1"
+            mapping = { type = "RGB", r = 17, g = 18, b = 19 }
+
+            [[patch]]
+            from = { universe = "dmx1", address = 18 }
+            to = { universe = "dmx1", address = 1 }
+            count = 3
+        "#);
+
+        assert_eq!(problems, vec!["Light b: mapping overlaps light a on universe dmx1 at 0-2 once patched"]);
+    }
+
+    #[test]
+    fn reports_offsets_past_the_end_of_the_universe() {
+        let config = config(r#"
+            [[lights]]
+            id = "far"
+            display_name = "Far"
+            universe = "dmx1"
+            profile = "far"
+            address = 2
+        "#);
+        let far = FixtureProfile::new(vec![ChannelDefinition::new(ChannelRole::Intensity, u16::MAX)]);
+        let error = resolve_lights(&config.lights, &HashMap::from([("far".to_string(), far)]), &config.universes, &PatchTable::default()).unwrap_err();
+
+        assert!(error.to_string().contains("Light far: address 2 puts channels at 65537, outside 1-512"), "{}", error);
+    }
+
+    #[test]
+    fn reports_channels_reused_within_a_light() {
+        let problems = problems(r#"
+            [[lights]]
+            id = "strip"
+            display_name = "Strip"
+            universe = "dmx1"
+            mapping = { type = "RGB", r = 5, g = 5, b = 6 }
+        "#);
+
+        assert_eq!(problems, vec!["Light strip: mapping uses 5 for more than one channel"]);
+    }
+}
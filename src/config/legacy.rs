@@ -0,0 +1,153 @@
+use anyhow::anyhow;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+// The universe and adapter the bridge drove before universes were configurable
+const DEFAULT_UNIVERSE: &str = "dmx1";
+const DEFAULT_SERIAL: &str = "AB0N3G14";
+
+// Lights used to be written with their mapping under `config` and no
+// universe, as there was only ever one. Rewrites them to use `mapping` and the
+// single configured universe, returning the ids of the lights changed. Files
+// from before universes were configurable get the one that was hardcoded.
+pub fn migrate(document: &mut DocumentMut) -> anyhow::Result<Vec<String>> {
+    let add_default = !document.contains_key("universes");
+    let universes: Vec<String> = match document.get("universes").and_then(Item::as_array_of_tables) {
+        Some(universes) => universes.iter()
+            .filter_map(|universe| universe.get("id").and_then(Item::as_str).map(str::to_string))
+            .collect(),
+        None if add_default => vec![DEFAULT_UNIVERSE.to_string()],
+        None => Vec::new(),
+    };
+
+    let Some(lights) = document.get_mut("lights").and_then(Item::as_array_of_tables_mut) else {
+        return Ok(Vec::new());
+    };
+
+    let mut migrated = Vec::new();
+    for light in lights.iter_mut() {
+        if !light.contains_key("config") {
+            continue;
+        }
+        let id = light.get("id").and_then(Item::as_str).unwrap_or("without an id").to_string();
+        if light.contains_key("mapping") {
+            return Err(anyhow!("Light {} has both the old `config` and a `mapping`", id));
+        }
+
+        if !light.contains_key("universe") {
+            let [universe] = universes.as_slice() else {
+                return Err(anyhow!("Light {} has no universe, and {} are configured to pick from", id, universes.len()));
+            };
+            light.insert("universe", value(universe.as_str()));
+        }
+        let mapping = light.remove("config").unwrap();
+        light.insert("mapping", mapping);
+
+        migrated.push(id);
+    }
+
+    if add_default && !migrated.is_empty() {
+        let mut universe = Table::new();
+        universe.insert("id", value(DEFAULT_UNIVERSE));
+        universe.insert("serial", value(DEFAULT_SERIAL));
+        // Right after [mqtt], where the new example keeps it
+        if let Some(position) = document.get("mqtt").and_then(Item::as_table).and_then(Table::position) {
+            universe.set_position(position);
+        }
+        let mut universes = ArrayOfTables::new();
+        universes.push(universe);
+        document.insert("universes", Item::ArrayOfTables(universes));
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::config::{resolve_lights, Config};
+
+    // config.example.toml as it was before universes and profiles
+    const LEGACY_EXAMPLE: &str = r#"
+[mqtt]
+host="192.168.0.33"
+username="mqtt"
+password="12345678"
+
+# Set to 15
+[[lights]]
+display_name="Par 2"
+id="par2"
+config.type = "RGBWDimmer"
+config.dimmer = 17
+config.r = 18
+config.g = 19
+config.b = 20
+config.w = 21
+
+
+[[lights]]
+display_name="Light Bar"
+id="light1"
+config.type = "RGBWDimmer"
+config.r = 2
+config.g = 3
+config.b = 4
+config.w = 5
+config.dimmer = 0
+
+# Set to 8
+[[lights]]
+display_name="Bar 2"
+id="bar2"
+config.type = "RGBDimmer"
+config.r = 9
+config.g = 10
+config.b = 11
+config.dimmer = 8
+
+
+
+# Set to 23
+[[lights]]
+display_name="Par 1"
+id="par1"
+config.type = "RGBWDimmer"
+config.dimmer = 25
+config.r = 26
+config.g = 27
+config.b = 28
+config.w = 29"#;
+
+    #[test]
+    fn migrates_the_legacy_example() {
+        let mut document: DocumentMut = LEGACY_EXAMPLE.parse().unwrap();
+        assert_eq!(migrate(&mut document).unwrap(), vec!["par2", "light1", "bar2", "par1"]);
+
+        let config: Config = toml::from_str(&document.to_string()).unwrap();
+        assert_eq!(config.universes.len(), 1);
+        assert_eq!((config.universes[0].id.as_str(), config.universes[0].serial.as_str()), ("dmx1", "AB0N3G14"));
+
+        let lights = resolve_lights(&config.lights, &HashMap::new(), &config.universes, &config.patch_table().unwrap()).unwrap();
+        assert!(lights.iter().all(|light| light.universe == "dmx1"));
+        let par2 = &lights[0].fixture;
+        assert_eq!(par2.profile.channels.iter().map(|channel| par2.address(channel).unwrap()).collect::<Vec<_>>(), vec![17, 18, 19, 20, 21]);
+
+        // Migrating again changes nothing
+        assert!(migrate(&mut document).unwrap().is_empty());
+    }
+
+    #[test]
+    fn picks_the_only_configured_universe() {
+        let legacy = |universes: &str| format!("{}\n[[lights]]\nid = \"par\"\nconfig = {{ type = \"Dimmer\", dimmer = 0 }}", universes);
+
+        let mut document: DocumentMut = legacy("[[universes]]\nid = \"stage\"\nserial = \"B\"").parse().unwrap();
+        migrate(&mut document).unwrap();
+        assert_eq!(document["lights"][0]["universe"].as_str(), Some("stage"));
+        assert_eq!(document["universes"].as_array_of_tables().unwrap().len(), 1);
+
+        let mut document: DocumentMut = legacy("universes = [{ id = \"a\", serial = \"A\" }, { id = \"b\", serial = \"B\" }]").parse().unwrap();
+        assert!(migrate(&mut document).is_err());
+    }
+}
@@ -1,14 +1,15 @@
-use std::{collections::HashMap, hash::Hash, sync::Arc};
+use std::{collections::HashMap, hash::Hash, sync::Arc, time::Instant};
 
 use log::{debug, error, info};
 use tokio::sync::{Mutex, RwLock};
 use tokio_util::sync::CancellationToken;
 
-use crate::{ config::LightSpecification, dmx::{DMXController, FTDIDMXController}, hass::{Color, ColorMode, HomeAssistantLightState, State}};
+use crate::{ config::LightSpecification, dmx::{DMXController, DMXControllerError, FTDIDMXController}, fixture::ChannelRole, hass::{Flash, HomeAssistantLightState}, patch::PatchTable};
 
 
 pub enum ControlMessage {
     LightState(String, HomeAssistantLightState),
+    Parameter(String, String, u16),
 }
 
 #[derive(PartialEq)]
@@ -21,67 +22,81 @@ enum LightState {
 struct LightObject {
     specification: LightSpecification,
     control_state: HomeAssistantLightState,
+    parameters: HashMap<String, u16>,
+    // End of a running flash, with the parameters to restore afterwards
+    flash: Option<(Instant, HashMap<String, u16>)>,
     state: LightState,
 }
 
 impl LightObject {
     pub fn new(specification: LightSpecification) -> Self {
         let control_state = HomeAssistantLightState::default_from_specification(&specification);
+        let parameters = specification.fixture.default_parameters();
         LightObject {
             specification,
             control_state,
+            parameters,
+            flash: None,
             state: LightState::Normal,
         }
     }
 
-    pub fn frame_values(&self) -> Vec<(u16, u8)> {
-        match &self.specification.mapping {
-            crate::config::LightChannelMapping::RGBWDimmer(mapping) => {
-                if self.control_state.state == State::Off {
-                    return vec![
-                        (mapping.dimmer, 0),
-                    ];
-                }
+    // Strobes the fixture at full rate until the flash runs out
+    fn start_flash(&mut self, flash: Flash) {
+        if !self.specification.fixture.profile.has(ChannelRole::Strobe) {
+            debug!("Light {} has no strobe channel to flash with", self.specification.id);
+            return;
+        }
 
-                let (r, g, b, w) = match self.control_state.color {
-                    Some(Color::RGBW { r, g, b, w }) => (r, g, b, w),
-                    _ => panic!("RGBWDimmer light must have RGBW color set"),
-                };
+        let saved = match self.flash.take() {
+            Some((_, saved)) => saved,
+            None => self.parameters.clone(),
+        };
+        self.flash = Some((Instant::now() + flash.duration(), saved));
 
-                let dimmer = self.control_state.brightness.unwrap();
+        self.parameters.insert("strobe".to_string(), 255);
+        if self.parameters.contains_key("shutter") {
+            self.parameters.insert("shutter".to_string(), 1);
+        }
+    }
 
-                vec![(mapping.r, r),
-                     (mapping.g, g),
-                     (mapping.b, b),
-                     (mapping.w, w),
-                     (mapping.dimmer, dimmer)]
-            }
-            crate::config::LightChannelMapping::RGBDimmer(mapping) => {
-                if self.control_state.state == State::Off {
-                    return vec![
-                        (mapping.dimmer, 0),
-                    ];
-                }
+    pub fn frame_values(&self) -> Vec<(u16, u8)> {
+        self.specification.fixture.frame_values(&self.control_state, &self.parameters)
+    }
+}
 
-                let (r, g, b) = match self.control_state.color {
-                    Some(Color::RGB { r, g, b }) => (r, g, b),
-                    _ => panic!("RGBDimmer light must have RGB color set"),
-                };
+async fn write_light(universes: &HashMap<String, FTDIDMXController>, patch: &PatchTable, light: &LightObject) -> Result<(), DMXControllerError> {
+    for (universe_id, values) in patch.apply(&light.specification.universe, light.frame_values()) {
+        if let Some(universe) = universes.get(&universe_id) {
+            universe.update_many(values).await?;
+        }
+    }
+    Ok(())
+}
 
-                let dimmer = self.control_state.brightness.unwrap();
+// Fixtures come before their segments, which share their channels and must
+// be written last to show
+fn drawing_order(lights: &HashMap<String, LightObject>) -> Vec<&LightObject> {
+    let mut ordered: Vec<&LightObject> = lights.values().collect();
+    ordered.sort_by_key(|light| light.specification.parent.is_some());
+    ordered
+}
 
-                vec![(mapping.r, r),
-                     (mapping.g, g),
-                     (mapping.b, b),
-                     (mapping.dimmer, dimmer)]
-            }
-        }
+// Writes a light followed by its segments, as the light's frame covers every
+// cell and would otherwise replace what the segments show
+async fn write_fixture(universes: &HashMap<String, FTDIDMXController>, patch: &PatchTable, lights: &HashMap<String, LightObject>, light_id: &str) -> Result<(), DMXControllerError> {
+    let fixture = drawing_order(lights).into_iter()
+        .filter(|light| light.specification.id == light_id || light.specification.parent.as_deref() == Some(light_id));
+    for light in fixture {
+        write_light(universes, patch, light).await?;
     }
+    Ok(())
 }
 
 pub struct LightController {
     universes: Arc<Mutex<HashMap<String, FTDIDMXController>>>,
     lights: Arc<RwLock<HashMap<String, LightObject>>>,
+    patch: Arc<RwLock<PatchTable>>,
     token: Option<CancellationToken>,
     handle: Option<tokio::task::JoinHandle<()>>,
     tx: Option<tokio::sync::mpsc::Sender<ControlMessage>>, 
@@ -95,13 +110,35 @@ impl LightController{
             tx: None,
             token: None,
             lights: Arc::new(RwLock::new(HashMap::new())),
+            patch: Arc::new(RwLock::new(PatchTable::default())),
         }
     }
 
     pub async fn add_universe(&mut self, id: &str, universe:FTDIDMXController) {
+        let mut universe = universe;
+        // Universes added after start begin sending right away, `redraw` fills them in
+        if self.handle.is_some() && let Err(e) = universe.start() {
+            error!("Failed to start DMX universe {}: {:?}", id, e);
+        }
         self.universes.lock().await.insert(id.to_string(), universe);
     }
 
+    // Stops a universe and closes its adapter
+    pub async fn remove_universe(&mut self, id: &str) -> anyhow::Result<()> {
+        let Some(mut universe) = self.universes.lock().await.remove(id) else {
+            return Err(anyhow::anyhow!("Universe {} not found", id));
+        };
+        if self.handle.is_some() {
+            universe.stop().await
+                .map_err(|e| anyhow::anyhow!("Failed to stop DMX universe {}: {:?}", id, e))?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_patch(&mut self, patch: PatchTable) {
+        *self.patch.write().await = patch;
+    }
+
     pub async fn add_light(&mut self, light: LightSpecification) -> anyhow::Result<()> {
         let mut lights = self.lights.write().await;
 
@@ -110,6 +147,51 @@ impl LightController{
         Ok(())
     }
 
+    // Swaps in a new set of lights. Unchanged lights carry on as they are and
+    // lights re-patched with the same profile keep their state; the rest
+    // start from their defaults. Call `redraw` afterwards.
+    pub async fn set_lights(&mut self, specifications: Vec<LightSpecification>) {
+        let mut lights = self.lights.write().await;
+        let mut previous = std::mem::take(&mut *lights);
+
+        for specification in specifications {
+            let light = match previous.remove(&specification.id) {
+                Some(light) if light.specification == specification => light,
+                Some(light) if light.specification.fixture.profile == specification.fixture.profile => {
+                    LightObject { specification, ..light }
+                }
+                _ => LightObject::new(specification),
+            };
+            lights.insert(light.specification.id.clone(), light);
+        }
+    }
+
+    // Rewrites every universe from scratch, so channels no light or patch
+    // entry uses any more go dark. Each universe is written in one go to
+    // avoid a blank frame in between.
+    pub async fn redraw(&self) -> anyhow::Result<()> {
+        let lights = self.lights.read().await;
+        let universes = self.universes.lock().await;
+        let patch = self.patch.read().await;
+
+        let mut frames: HashMap<&str, Vec<(u16, u8)>> = universes.keys()
+            .map(|id| (id.as_str(), (0..512).map(|address| (address, 0)).collect()))
+            .collect();
+        for light in drawing_order(&lights) {
+            for (universe_id, values) in patch.apply(&light.specification.universe, light.frame_values()) {
+                if let Some(frame) = frames.get_mut(universe_id.as_str()) {
+                    frame.extend(values);
+                }
+            }
+        }
+
+        for (id, values) in frames {
+            universes[id].update_many(values).await
+                .map_err(|e| anyhow::anyhow!("Failed to redraw universe {}: {:?}", id, e))?;
+        }
+        Ok(())
+    }
+
     pub async fn add_lights(&mut self, lights: Vec<LightSpecification>) -> anyhow::Result<()> {
         let mut lights_map = self.lights.write().await;
 
@@ -130,6 +212,15 @@ impl LightController{
         lights.iter().map(|(id, state)| (id.clone(), state.control_state.clone())).collect()
     }
 
+    pub async fn get_all_parameters(&self) -> HashMap<String, HashMap<String, u16>> {
+        let lights = self.lights.read().await;
+        lights.iter().map(|(id, light)| (id.clone(), light.parameters.clone())).collect()
+    }
+
+    pub async fn update_parameter(&mut self, light_id: &str, parameter: &str, value: u16) -> anyhow::Result<()> {
+        self.post_message(ControlMessage::Parameter(light_id.to_string(), parameter.to_string(), value)).await
+    }
+
     pub async fn update_light_state(&mut self, light_id: &str, state: HomeAssistantLightState) -> anyhow::Result<()> {
         self.post_message(ControlMessage::LightState(light_id.to_string(), state)).await
     }
@@ -146,6 +237,7 @@ impl LightController{
     pub fn start(&mut self) -> anyhow::Result<()> {
         let universes = self.universes.clone();
         let lights = self.lights.clone();
+        let patch = self.patch.clone();
         
         let (tx, mut rx) = tokio::sync::mpsc::channel::<ControlMessage>(100);
         self.tx = Some(tx);
@@ -164,9 +256,10 @@ impl LightController{
             {
                 let lights = lights.read().await;
                 let universes = universes.lock().await;
-                for (id, light) in lights.iter() {
-                    if let Some(universe) = universes.get(&light.specification.universe) {
-                        universe.update_many(light.frame_values()).await.unwrap();
+                let patch = patch.read().await;
+                for light in drawing_order(&lights) {
+                    if let Err(e) = write_light(&universes, &patch, light).await {
+                        error!("Failed to write light {}: {:?}", light.specification.id, e);
                     }
                 }
             }
@@ -200,12 +293,26 @@ impl LightController{
 
 
                                         light.control_state.update_with(&state);
+                                        if let Some(flash) = state.flash {
+                                            light.start_flash(flash);
+                                        }
+
+                                        // Wheel fixtures show the slot closest to the requested color
+                                        let fixture = &light.specification.fixture;
+                                        if fixture.profile.is_wheel_colored()
+                                            && let Some(slot) = state.color.as_ref().and_then(|color| fixture.nearest_slot(color)) {
+                                            light.parameters.insert("color_wheel".to_string(), slot);
+                                        }
 
-                                        if light.state == LightState::Normal {
-                                            let universe = light.specification.universe.clone();
-                                            if let Some(universe) = universes.lock().await.get_mut(&universe) {
-                                                universe.update_many(light.frame_values()).await.unwrap();
-                                            }
+                                        // Commands to a whole pixel fixture reach each of its segments
+                                        let command = light.specification.fixture.profile.cell_command(&state);
+                                        for segment in lights.values_mut().filter(|segment| segment.specification.parent.as_ref() == Some(&light_id)) {
+                                            segment.control_state.update_with(&command);
+                                        }
+
+                                        if lights[&light_id].state == LightState::Normal
+                                            && let Err(e) = write_fixture(&*universes.lock().await, &*patch.read().await, &lights, &light_id).await {
+                                            error!("Failed to write light {}: {:?}", light_id, e);
                                         }
 
                                         // if let Some(effect) = &light.control_state.effect {
@@ -218,11 +325,53 @@ impl LightController{
                                         error!("Light with ID {} not found", light_id);
                                     }
                                 }
+                                ControlMessage::Parameter(light_id, parameter, value) => {
+                                    info!("Received {} = {} for light {}", parameter, value, light_id);
+                                    let mut lights = lights.write().await;
+                                    if let Some(light) = lights.get_mut(&light_id) {
+                                        let Some(value) = light.specification.fixture.clamp_parameter(&parameter, value) else {
+                                            error!("Light {} has no parameter {}", light_id, parameter);
+                                            continue;
+                                        };
+                                        // Keep the light's color in step with a wheel picked directly
+                                        if parameter == "color_wheel" && light.specification.fixture.profile.is_wheel_colored()
+                                            && let Some(color) = light.specification.fixture.slot_color(value) {
+                                            light.control_state.color_mode = Some(color.mode());
+                                            light.control_state.color = Some(color);
+                                        }
+                                        // Changes made during a flash outlast it
+                                        if let Some((_, saved)) = light.flash.as_mut() {
+                                            saved.insert(parameter.clone(), value);
+                                        }
+                                        light.parameters.insert(parameter, value);
+                                        if let Err(e) = write_fixture(&*universes.lock().await, &*patch.read().await, &lights, &light_id).await {
+                                            error!("Failed to write light {}: {:?}", light_id, e);
+                                        }
+                                    } else {
+                                        error!("Light with ID {} not found", light_id);
+                                    }
+                                }
                             }
                         }
                     },
                     _ = interval.tick() => {
-                        let lights = lights.read().await;
+                        let mut lights = lights.write().await;
+                        let mut ended = Vec::new();
+                        for light in lights.values_mut() {
+                            let Some((until, _)) = &light.flash else { continue };
+                            if *until > Instant::now() {
+                                continue;
+                            }
+                            let (_, saved) = light.flash.take().unwrap();
+                            light.parameters = saved;
+                            ended.push(light.specification.id.clone());
+                        }
+                        for light_id in ended {
+                            if let Err(e) = write_fixture(&*universes.lock().await, &*patch.read().await, &lights, &light_id).await {
+                                error!("Failed to end flash of light {}: {:?}", light_id, e);
+                            }
+                        }
+
                         for (id, light) in lights.iter() {
                             // if light.state == LightState::TurningOn {
                             //     light.state = LightState::Normal;
@@ -1,10 +1,57 @@
-use std::{error::Error, fmt::Display, sync::{Arc}, thread};
+use std::{error::Error, fmt::Display, fs, path::{Path, PathBuf}, sync::{Arc}, thread};
 
 use libftd2xx::{Ft232r, FtdiCommon};
 use log::{debug, info};
 use tokio::sync::RwLock;
 use tokio_util::sync::CancellationToken;
 
+// A USB serial port, as the kernel sees it
+#[derive(Debug, Clone)]
+pub struct SerialPort {
+    pub path: PathBuf,
+    pub serial_number: Option<String>,
+    pub driver: Option<String>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl SerialPort {
+    // FTDI adapters claimed by the kernel's serial driver are hidden from D2XX
+    pub fn needs_unbinding(&self) -> bool {
+        self.driver.as_deref() == Some("ftdi_sio")
+    }
+}
+
+// USB serial ports from sysfs. Each tty's device is walked up to the USB
+// device it belongs to, which carries the ids and serial number.
+pub fn serial_ports() -> Vec<SerialPort> {
+    serial_ports_in(Path::new("/sys/class/tty"))
+}
+
+fn serial_ports_in(class: &Path) -> Vec<SerialPort> {
+    let Ok(entries) = fs::read_dir(class) else { return Vec::new() };
+    let read = |path: &Path| fs::read_to_string(path).ok().map(|value| value.trim().to_string());
+    let hex = |path: &Path| read(path).and_then(|value| u16::from_str_radix(&value, 16).ok());
+
+    let mut ports: Vec<SerialPort> = entries.flatten()
+        .filter_map(|entry| {
+            let device = fs::canonicalize(entry.path().join("device")).ok()?;
+            let usb = device.ancestors().find(|dir| dir.join("idVendor").exists())?;
+            let driver = fs::read_link(device.join("driver")).ok()
+                .and_then(|driver| driver.file_name().map(|name| name.to_string_lossy().to_string()));
+            Some(SerialPort {
+                path: Path::new("/dev").join(entry.file_name()),
+                serial_number: read(&usb.join("serial")),
+                driver,
+                vendor_id: hex(&usb.join("idVendor"))?,
+                product_id: hex(&usb.join("idProduct"))?,
+            })
+        })
+        .collect();
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    ports
+}
+
 pub trait DMXDriver {
     fn init(&mut self) -> anyhow::Result<()>;
     fn write_frame(&mut self, data: &[u8]) -> anyhow::Result<()>;
@@ -187,4 +234,40 @@ impl DMXController for FTDIDMXController {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn finds_usb_serial_ports_in_sysfs() {
+        let root = std::env::temp_dir().join(format!("dmx3-sysfs-{}", std::process::id()));
+        let usb = root.join("devices/usb1/1-1");
+        let port = usb.join("1-1:1.0/ttyUSB0");
+        fs::create_dir_all(&port).unwrap();
+        fs::create_dir_all(root.join("drivers/ftdi_sio")).unwrap();
+        fs::create_dir_all(root.join("devices/platform/serial8250/ttyS0")).unwrap();
+        fs::write(usb.join("idVendor"), "0403\n").unwrap();
+        fs::write(usb.join("idProduct"), "6001\n").unwrap();
+        fs::write(usb.join("serial"), "AB0N3G14\n").unwrap();
+        symlink(root.join("drivers/ftdi_sio"), port.join("driver")).unwrap();
+
+        let class = root.join("class/tty");
+        for (name, device) in [("ttyUSB0", port), ("ttyS0", root.join("devices/platform/serial8250/ttyS0"))] {
+            fs::create_dir_all(class.join(name)).unwrap();
+            symlink(device, class.join(name).join("device")).unwrap();
+        }
+
+        let ports = serial_ports_in(&class);
+        fs::remove_dir_all(&root).unwrap();
+
+        // Ports without a USB device, like the onboard UART, are left out
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports[0].path, Path::new("/dev/ttyUSB0"));
+        assert_eq!(ports[0].serial_number.as_deref(), Some("AB0N3G14"));
+        assert_eq!((ports[0].vendor_id, ports[0].product_id), (0x0403, 0x6001));
+        assert!(ports[0].needs_unbinding());
+    }
+}
@@ -0,0 +1,1198 @@
+mod ofl;
+mod qxf;
+
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use anyhow::anyhow;
+use log::debug;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::color::{self, Xyz};
+use crate::config::{self, LightChannelMapping};
+use crate::hass::{Color, ColorMode, HomeAssistantLightState, State};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelRole {
+    Intensity,
+    Red,
+    Green,
+    Blue,
+    White,
+    #[serde(rename = "warm_white")]
+    WarmWhite,
+    #[serde(rename = "cool_white")]
+    CoolWhite,
+    Amber,
+    Lime,
+    Cyan,
+    #[serde(rename = "uv")]
+    UV,
+    #[serde(rename = "ct")]
+    CT,
+    Strobe,
+    Shutter,
+    Pan,
+    #[serde(rename = "pan_fine")]
+    PanFine,
+    Tilt,
+    #[serde(rename = "tilt_fine")]
+    TiltFine,
+    #[serde(rename = "pan_tilt_speed")]
+    PanTiltSpeed,
+    #[serde(rename = "color_wheel")]
+    ColorWheel,
+    // Any other fixture feature (zoom, focus, macros, ...) named by the profile
+    Aux,
+    Switch,
+}
+
+impl ChannelRole {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChannelRole::Intensity => "intensity",
+            ChannelRole::Red => "red",
+            ChannelRole::Green => "green",
+            ChannelRole::Blue => "blue",
+            ChannelRole::White => "white",
+            ChannelRole::WarmWhite => "warm_white",
+            ChannelRole::CoolWhite => "cool_white",
+            ChannelRole::Amber => "amber",
+            ChannelRole::Lime => "lime",
+            ChannelRole::Cyan => "cyan",
+            ChannelRole::UV => "uv",
+            ChannelRole::CT => "ct",
+            ChannelRole::Strobe => "strobe",
+            ChannelRole::Shutter => "shutter",
+            ChannelRole::Pan => "pan",
+            ChannelRole::PanFine => "pan_fine",
+            ChannelRole::Tilt => "tilt",
+            ChannelRole::TiltFine => "tilt_fine",
+            ChannelRole::PanTiltSpeed => "pan_tilt_speed",
+            ChannelRole::ColorWheel => "color_wheel",
+            ChannelRole::Aux => "aux",
+            ChannelRole::Switch => "switch",
+        }
+    }
+
+    // Typical CIE xy chromaticity and relative luminance of an emitter at full output
+    fn default_emitter(&self) -> Option<((f32, f32), f32)> {
+        match self {
+            ChannelRole::Red => Some(((0.640, 0.330), 0.2126)),
+            ChannelRole::Green => Some(((0.300, 0.600), 0.7152)),
+            ChannelRole::Blue => Some(((0.150, 0.060), 0.0722)),
+            ChannelRole::White => Some(((0.3127, 0.3290), 1.0)),
+            ChannelRole::Amber => Some(((0.570, 0.420), 0.5)),
+            ChannelRole::Lime => Some(((0.410, 0.550), 0.8)),
+            ChannelRole::Cyan => Some(((0.070, 0.550), 0.5)),
+            _ => None,
+        }
+    }
+
+    pub fn is_emitter(&self) -> bool {
+        self.default_emitter().is_some() || matches!(self, ChannelRole::WarmWhite | ChannelRole::CoolWhite)
+    }
+
+    // Roles that Home Assistant can't drive through a light are exposed as
+    // entities of their own. Fine channels share the parameter of their coarse channel.
+    pub fn parameter(&self) -> Option<&'static str> {
+        match self {
+            ChannelRole::UV | ChannelRole::PanTiltSpeed | ChannelRole::ColorWheel
+                | ChannelRole::Strobe | ChannelRole::Shutter => Some(self.name()),
+            ChannelRole::Pan | ChannelRole::PanFine => Some("pan"),
+            ChannelRole::Tilt | ChannelRole::TiltFine => Some("tilt"),
+            _ => None,
+        }
+    }
+
+    pub fn is_fine(&self) -> bool {
+        matches!(self, ChannelRole::PanFine | ChannelRole::TiltFine)
+    }
+
+    // Positions are 16-bit, split over a coarse and an optional fine channel
+    fn is_position(&self) -> bool {
+        matches!(self, ChannelRole::Pan | ChannelRole::PanFine | ChannelRole::Tilt | ChannelRole::TiltFine)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ChannelDefinition {
+    pub role: ChannelRole,
+    pub offset: u16,
+
+    // Value written while the role is not driven by Home Assistant
+    #[serde(default)]
+    pub default: u8,
+
+    // DMX range the role's 0-255 value is scaled into
+    #[serde(default = "full_range")]
+    pub range: (u8, u8),
+
+    // Measured CIE xy chromaticity and relative luminance of color emitters
+    pub chromaticity: Option<(f32, f32)>,
+    pub luminance: Option<f32>,
+
+    // Slots of a stepped color wheel
+    #[serde(default)]
+    pub slots: Vec<WheelSlot>,
+
+    // Value that closes the shutter of a combined shutter/strobe channel
+    pub closed: Option<u8>,
+
+    // Name and entity type of auxiliary channels, with the options of selects
+    pub name: Option<String>,
+    #[serde(rename = "type", default)]
+    pub kind: AuxKind,
+    #[serde(default)]
+    pub options: Vec<AuxOption>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuxKind {
+    #[default]
+    Number,
+    Select,
+    Switch,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AuxOption {
+    pub name: String,
+    pub range: (u8, u8),
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct WheelSlot {
+    pub name: String,
+    pub range: (u8, u8),
+    // Approximate color of the slot as seen from the front
+    pub color: (u8, u8, u8),
+}
+
+impl WheelSlot {
+    fn value(&self) -> u8 {
+        midpoint(self.range)
+    }
+}
+
+impl AuxOption {
+    fn value(&self) -> u8 {
+        midpoint(self.range)
+    }
+}
+
+fn midpoint((min, max): (u8, u8)) -> u8 {
+    ((min as u16 + max as u16) / 2) as u8
+}
+
+// "#rrggbb" as written by OFL and QLC+ for wheel slot colors
+fn parse_hex_color(hex: &str) -> Option<(u8, u8, u8)> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let component = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some((component(0)?, component(2)?, component(4)?))
+}
+
+fn full_range() -> (u8, u8) {
+    (0, 255)
+}
+
+impl ChannelDefinition {
+    pub fn new(role: ChannelRole, offset: u16) -> Self {
+        ChannelDefinition { role, offset, default: 0, range: full_range(), chromaticity: None, luminance: None, slots: Vec::new(), closed: None, name: None, kind: AuxKind::default(), options: Vec::new() }
+    }
+
+    // Whether an imported capability over `range` is driven, either scaled
+    // into the channel's range or picked as a wheel slot
+    fn covers(&self, (min, max): (u8, u8)) -> bool {
+        match self.role {
+            ChannelRole::ColorWheel => self.slots.iter().any(|slot| slot.range == (min, max)),
+            _ => self.range.0 <= min && max <= self.range.1,
+        }
+    }
+
+    // Parameter driving the channel, if Home Assistant sets it through its own entity
+    pub fn parameter(&self) -> Option<&str> {
+        match self.role {
+            ChannelRole::Aux => self.name.as_deref(),
+            role => role.parameter(),
+        }
+    }
+
+    pub fn emitter(&self) -> Option<Xyz> {
+        let ((x, y), luminance) = self.role.default_emitter()?;
+        let (x, y) = self.chromaticity.unwrap_or((x, y));
+        Some(color::xy_to_xyz(x, y, self.luminance.unwrap_or(luminance)))
+    }
+
+    pub fn scale(&self, value: u8) -> u8 {
+        let (min, max) = self.range;
+        (min as i32 + (max as i32 - min as i32) * value as i32 / 255) as u8
+    }
+
+    fn aux_kind(&self) -> ParameterKind {
+        match self.kind {
+            AuxKind::Number => ParameterKind::Number { min: 0, max: 255 },
+            AuxKind::Select => ParameterKind::Select(self.options.iter().map(|option| option.name.clone()).collect()),
+            AuxKind::Switch => ParameterKind::Switch,
+        }
+    }
+
+    // Parameter value matching the channel's default
+    fn aux_default(&self) -> u16 {
+        match self.kind {
+            AuxKind::Number => {
+                let (min, max) = self.range;
+                if max == min {
+                    0
+                } else {
+                    ((self.default.clamp(min.min(max), min.max(max)) as i32 - min as i32) * 255 / (max as i32 - min as i32)) as u16
+                }
+            }
+            AuxKind::Select => self.options.iter()
+                .position(|option| (option.range.0..=option.range.1).contains(&self.default))
+                .unwrap_or(0) as u16,
+            AuxKind::Switch => (self.default != self.range.0) as u16,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct FixtureProfile {
+    pub channels: Vec<ChannelDefinition>,
+
+    // Color temperature endpoints of tunable white fixtures
+    #[serde(default = "default_warm_kelvin")]
+    pub warm_kelvin: u16,
+    #[serde(default = "default_cool_kelvin")]
+    pub cool_kelvin: u16,
+
+    // Exponent applied to brightness when it has to be emulated by scaling
+    // the emitters of fixtures without an intensity channel
+    #[serde(default = "default_dimming_gamma")]
+    pub dimming_gamma: f32,
+
+    // RGBW fixtures receiving any other color have their white channel taken
+    // from the component common to red, green and blue. `white_point` is the
+    // RGB mix that looks like the white emitter, `white_gain` how bright the
+    // white emitter is compared to that mix.
+    #[serde(default = "default_white_extraction")]
+    pub white_extraction: bool,
+    #[serde(default = "default_white_point")]
+    pub white_point: (u8, u8, u8),
+    #[serde(default = "default_white_gain")]
+    pub white_gain: f32,
+
+    // Repeated pixel cells of LED bars and strips
+    pub cells: Option<CellLayout>,
+}
+
+// `count` identical blocks of `channels`, the first at offset `start` and each
+// following one `stride` channels further along
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct CellLayout {
+    pub count: u16,
+    pub start: u16,
+    pub stride: Option<u16>,
+    pub channels: Vec<ChannelDefinition>,
+}
+
+impl CellLayout {
+    fn stride(&self) -> u16 {
+        self.stride.unwrap_or_else(|| self.channels.iter().map(|channel| channel.offset.saturating_add(1)).max().unwrap_or(1))
+    }
+
+    // Offset of the last channel of the last cell, which may not fit an offset
+    fn last_offset(&self) -> u32 {
+        let last = self.channels.iter().map(|channel| channel.offset as u32).max().unwrap_or(0);
+        self.start as u32 + self.count.saturating_sub(1) as u32 * self.stride() as u32 + last
+    }
+
+    // Channels whose offset doesn't fit are left out, `problems` reports them
+    fn cell_channels(&self, cell: u16) -> impl Iterator<Item = ChannelDefinition> + '_ {
+        let base = cell.checked_mul(self.stride()).and_then(|offset| offset.checked_add(self.start));
+        self.channels.iter().filter_map(move |channel| {
+            Some(ChannelDefinition { offset: base?.checked_add(channel.offset)?, ..channel.clone() })
+        })
+    }
+}
+
+fn default_white_extraction() -> bool {
+    true
+}
+
+fn default_white_point() -> (u8, u8, u8) {
+    (255, 255, 255)
+}
+
+fn default_white_gain() -> f32 {
+    1.0
+}
+
+fn default_dimming_gamma() -> f32 {
+    1.0
+}
+
+fn default_warm_kelvin() -> u16 {
+    2700
+}
+
+fn default_cool_kelvin() -> u16 {
+    6500
+}
+
+impl FixtureProfile {
+    pub fn new(channels: Vec<ChannelDefinition>) -> Self {
+        FixtureProfile {
+            channels,
+            warm_kelvin: default_warm_kelvin(),
+            cool_kelvin: default_cool_kelvin(),
+            dimming_gamma: default_dimming_gamma(),
+            white_extraction: default_white_extraction(),
+            white_point: default_white_point(),
+            white_gain: default_white_gain(),
+            cells: None,
+        }
+    }
+
+    pub fn cell_count(&self) -> u16 {
+        self.cells.as_ref().map(|cells| cells.count).unwrap_or(0)
+    }
+
+    // The whole fixture with every cell laid out as plain channels, so that
+    // all cells follow the same color
+    pub fn flattened(&self) -> FixtureProfile {
+        let Some(cells) = &self.cells else { return self.clone() };
+        let mut channels = self.channels.clone();
+        for cell in 0..cells.count {
+            channels.extend(cells.cell_channels(cell));
+        }
+        FixtureProfile { channels, cells: None, ..self.clone() }
+    }
+
+    // A single cell as a fixture of its own, without the shared channels
+    pub fn cell(&self, cell: u16) -> Option<FixtureProfile> {
+        let cells = self.cells.as_ref().filter(|cells| cell < cells.count)?;
+        Some(FixtureProfile { channels: cells.cell_channels(cell).collect(), cells: None, ..self.clone() })
+    }
+
+    // Mistakes in the channel map that would otherwise only show as odd output
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        // Checked first, as laying out that many cells is what the rest works on
+        if let Some(cells) = self.cells.as_ref().filter(|cells| cells.count > 0 && cells.last_offset() >= 512) {
+            problems.push(format!("{} cells run past the end of the universe, the last channel is at offset {}", cells.count, cells.last_offset()));
+            return problems;
+        }
+        let channels = self.flattened().channels;
+
+        let mut offsets: Vec<u16> = channels.iter().map(|channel| channel.offset).collect();
+        offsets.sort();
+        let mut shared: Vec<u16> = offsets.windows(2).filter(|pair| pair[0] == pair[1]).map(|pair| pair[0]).collect();
+        shared.dedup();
+        for offset in shared {
+            problems.push(format!("offset {} is used by more than one channel", offset));
+        }
+
+        if self.white_gain <= 0.0 {
+            problems.push(format!("white_gain ({}) must be above 0", self.white_gain));
+        }
+
+        // Kelvin become mireds by division, and the warm end must be the larger
+        if self.warm_kelvin == 0 || self.warm_kelvin >= self.cool_kelvin {
+            problems.push(format!("warm_kelvin ({}) must be above 0 and below cool_kelvin ({})", self.warm_kelvin, self.cool_kelvin));
+        }
+
+        let mut parameters: Vec<&str> = Vec::new();
+        for channel in self.channels.iter().chain(self.cells.iter().flat_map(|cells| cells.channels.iter())) {
+            let role = channel.role.name();
+            if channel.role == ChannelRole::Aux && channel.name.is_none() {
+                problems.push(format!("aux channel at offset {} has no name", channel.offset));
+            }
+            if channel.role == ChannelRole::ColorWheel && channel.slots.is_empty() {
+                problems.push(format!("{} channel at offset {} has no slots", role, channel.offset));
+            }
+            if channel.role == ChannelRole::Aux && channel.kind == AuxKind::Select && channel.options.is_empty() {
+                problems.push(format!("select channel {} has no options", channel.name.as_deref().unwrap_or(role)));
+            }
+            let ranges = channel.slots.iter().map(|slot| (&slot.name, slot.range))
+                .chain(channel.options.iter().map(|option| (&option.name, option.range)));
+            for (name, (min, max)) in ranges {
+                if min > max {
+                    problems.push(format!("{} of the {} channel has range {}-{}, min must not exceed max", name, role, min, max));
+                }
+            }
+
+            // Fine channels share the parameter of their coarse channel
+            if let Some(parameter) = channel.parameter().filter(|_| !channel.role.is_fine()) {
+                if parameters.contains(&parameter) {
+                    problems.push(format!("more than one channel sets parameter {}", parameter));
+                }
+                parameters.push(parameter);
+            }
+        }
+
+        problems
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to open fixture profile {}: {}", path.display(), e))?;
+        Ok(config::parse_toml(path, &contents)?)
+    }
+
+    // Loads every `*.toml` profile in a directory, keyed by file stem
+    pub fn load_dir(dir: &Path) -> anyhow::Result<HashMap<String, FixtureProfile>> {
+        let mut profiles = HashMap::new();
+
+        for entry in fs::read_dir(dir)
+            .map_err(|e| anyhow!("Unable to read profile directory {}: {}", dir.display(), e))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                debug!("Loading fixture profile {} from {}", name, path.display());
+                profiles.insert(name, FixtureProfile::load(&path)?);
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    pub fn channel(&self, role: ChannelRole) -> Option<&ChannelDefinition> {
+        self.channels.iter().find(|channel| channel.role == role)
+    }
+
+    pub fn has(&self, role: ChannelRole) -> bool {
+        self.channel(role).is_some()
+    }
+
+    // Profiles made of a single relay channel are exposed as switches, not lights
+    pub fn is_switch(&self) -> bool {
+        self.has(ChannelRole::Switch) && self.channels.len() == 1
+    }
+
+    // Fixtures with more emitters than Home Assistant knows about get their
+    // color solved from the emitters' chromaticities
+    pub fn is_multi_emitter(&self) -> bool {
+        self.has(ChannelRole::Amber) || self.has(ChannelRole::Lime) || self.has(ChannelRole::Cyan)
+    }
+
+    // Fixtures whose only source of color is a stepped color wheel
+    pub fn is_wheel_colored(&self) -> bool {
+        let rgb = self.has(ChannelRole::Red) && self.has(ChannelRole::Green) && self.has(ChannelRole::Blue);
+        !rgb && self.wheel_slots().is_some()
+    }
+
+    pub fn wheel_slots(&self) -> Option<&[WheelSlot]> {
+        self.channel(ChannelRole::ColorWheel)
+            .map(|channel| channel.slots.as_slice())
+            .filter(|slots| !slots.is_empty())
+    }
+
+    // Modes advertised to Home Assistant. Anything beyond the native mode is
+    // converted into the fixture's channels.
+    pub fn supported_color_modes(&self) -> Vec<ColorMode> {
+        match self.color_mode() {
+            ColorMode::HS => vec![ColorMode::HS, ColorMode::RGB, ColorMode::XY],
+            mode @ (ColorMode::RGB | ColorMode::RGBW | ColorMode::RGBWW) => {
+                vec![mode, ColorMode::HS, ColorMode::XY, ColorMode::ColorTemp]
+            }
+            mode => vec![mode],
+        }
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        let rgb = self.has(ChannelRole::Red) && self.has(ChannelRole::Green) && self.has(ChannelRole::Blue);
+
+        let tunable_white = (self.has(ChannelRole::WarmWhite) && self.has(ChannelRole::CoolWhite))
+            || (self.has(ChannelRole::CT) && self.has(ChannelRole::Intensity));
+
+        if rgb && self.is_multi_emitter() {
+            ColorMode::RGB
+        } else if rgb && self.has(ChannelRole::WarmWhite) && self.has(ChannelRole::CoolWhite) {
+            ColorMode::RGBWW
+        } else if rgb && self.has(ChannelRole::White) {
+            ColorMode::RGBW
+        } else if rgb {
+            ColorMode::RGB
+        } else if self.is_wheel_colored() {
+            ColorMode::HS
+        } else if tunable_white {
+            ColorMode::ColorTemp
+        } else if self.has(ChannelRole::Intensity) || self.channels.iter().any(|channel| channel.role.is_emitter()) {
+            ColorMode::Brightness
+        } else {
+            ColorMode::OnOff
+        }
+    }
+
+    pub fn min_mireds(&self) -> u16 {
+        (1_000_000 / self.cool_kelvin as u32) as u16
+    }
+
+    pub fn max_mireds(&self) -> u16 {
+        (1_000_000 / self.warm_kelvin as u32) as u16
+    }
+
+    // Share of the cool emitter needed for a color temperature, from 0.0 (warm) to 1.0 (cool)
+    pub fn cool_share(&self, mireds: u16) -> f32 {
+        let mireds = mireds.clamp(self.min_mireds(), self.max_mireds());
+        if self.max_mireds() == self.min_mireds() {
+            return 0.5;
+        }
+        (self.max_mireds() - mireds) as f32 / (self.max_mireds() - self.min_mireds()) as f32
+    }
+
+    // Moves the white common to an RGB color onto the white emitter, then
+    // scales everything back up to the original peak level
+    pub fn extract_white(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8, u8) {
+        let (wr, wg, wb) = self.white_point;
+        let white = [wr as f32 / 255.0, wg as f32 / 255.0, wb as f32 / 255.0];
+        let mut rgb = [r as f32, g as f32, b as f32];
+
+        let w = rgb.iter().zip(white.iter())
+            .filter(|(_, white)| **white > 0.0)
+            .map(|(value, white)| value / (white * self.white_gain))
+            .fold(255.0, f32::min);
+
+        for (value, white) in rgb.iter_mut().zip(white.iter()) {
+            *value = (*value - w * white * self.white_gain).max(0.0);
+        }
+
+        let peak = r.max(g).max(b) as f32;
+        let extracted_peak = rgb.iter().cloned().fold(w, f32::max);
+        let scale = if extracted_peak > 0.0 { peak / extracted_peak } else { 0.0 };
+
+        let level = |value: f32| (value * scale).round().clamp(0.0, 255.0) as u8;
+        (level(rgb[0]), level(rgb[1]), level(rgb[2]), level(w))
+    }
+
+    // What a command to the whole fixture means for each of its cells. Cells
+    // of fixtures with a master dimmer stay at full level below it.
+    pub fn cell_command(&self, command: &HomeAssistantLightState) -> HomeAssistantLightState {
+        let mut command = command.clone();
+        if self.has(ChannelRole::Intensity) && command.brightness.is_some() {
+            command.brightness = Some(255);
+        }
+        command.flash = None;
+        command
+    }
+
+    pub fn default_state(&self) -> HomeAssistantLightState {
+        let color = match self.color_mode() {
+            ColorMode::RGBWW => Some(Color::RGBWW { r: 255, g: 255, b: 255, c: 255, w: 255 }),
+            ColorMode::RGBW => Some(Color::RGBW { r: 255, g: 255, b: 255, w: 255 }),
+            ColorMode::RGB => Some(Color::RGB { r: 255, g: 255, b: 255 }),
+            ColorMode::HS => Some(Color::HS { h: 0.0, s: 0.0 }),
+            _ => None,
+        };
+
+        let color_temp = match self.color_mode() {
+            ColorMode::ColorTemp => Some((self.min_mireds() + self.max_mireds()) / 2),
+            _ => None,
+        };
+
+        HomeAssistantLightState {
+            brightness: Some(255),
+            color_mode: Some(self.color_mode()),
+            color,
+            color_temp,
+            // Don't start fans and other relay loads on their own
+            state: if self.is_switch() { State::Off } else { State::On },
+            effect: None,
+            flash: None,
+        }
+    }
+}
+
+// Where a fixture profile comes from in the config file
+#[derive(Debug, Clone)]
+pub enum ProfileSource {
+    Inline(FixtureProfile),
+    File { file: PathBuf },
+    Ofl { ofl: PathBuf, mode: Option<String> },
+    Qxf { qxf: PathBuf, mode: Option<String> },
+    Mapping(LightChannelMapping),
+}
+
+// Picked by key rather than by trying each kind in turn, so a mistake in a
+// profile is reported as itself instead of as matching no kind at all
+impl<'de> Deserialize<'de> for ProfileSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct File { file: PathBuf }
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Ofl { ofl: PathBuf, mode: Option<String> }
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Qxf { qxf: PathBuf, mode: Option<String> }
+
+        let value = toml::Value::deserialize(deserializer)?;
+        let keys: Vec<&str> = ["channels", "file", "ofl", "qxf", "type"].into_iter()
+            .filter(|key| value.get(key).is_some())
+            .collect();
+
+        let source = match keys.as_slice() {
+            ["channels"] => FixtureProfile::deserialize(value).map(ProfileSource::Inline),
+            ["file"] => File::deserialize(value).map(|File { file }| ProfileSource::File { file }),
+            ["ofl"] => Ofl::deserialize(value).map(|Ofl { ofl, mode }| ProfileSource::Ofl { ofl, mode }),
+            ["qxf"] => Qxf::deserialize(value).map(|Qxf { qxf, mode }| ProfileSource::Qxf { qxf, mode }),
+            ["type"] => LightChannelMapping::deserialize(value).map(ProfileSource::Mapping),
+            [] => return Err(de::Error::custom("expected `channels`, a `file`, `ofl` or `qxf` to load from, or a mapping `type`")),
+            _ => return Err(de::Error::custom(format!("expected only one of `{}`", keys.join("`, `")))),
+        };
+        source.map_err(|e| de::Error::custom(e.message()))
+    }
+}
+
+impl ProfileSource {
+    // Makes file paths relative to the directory of the config they were read from
+    pub fn relative_to(&mut self, dir: &Path) {
+        match self {
+            ProfileSource::File { file: path } | ProfileSource::Ofl { ofl: path, .. } | ProfileSource::Qxf { qxf: path, .. } => {
+                *path = dir.join(&*path);
+            }
+            ProfileSource::Inline(_) | ProfileSource::Mapping(_) => {}
+        }
+    }
+
+    // The profile, along with the channels and capabilities of an imported
+    // definition that it leaves undriven
+    pub fn load(&self) -> anyhow::Result<(FixtureProfile, Vec<String>)> {
+        match self {
+            ProfileSource::Inline(profile) => Ok((profile.clone(), Vec::new())),
+            ProfileSource::File { file } => Ok((FixtureProfile::load(file)?, Vec::new())),
+            ProfileSource::Ofl { ofl, mode } => ofl::load(ofl, mode.as_deref()),
+            ProfileSource::Qxf { qxf, mode } => qxf::load(qxf, mode.as_deref()),
+            ProfileSource::Mapping(mapping) => Ok((mapping.to_profile(), Vec::new())),
+        }
+    }
+}
+
+// A profile patched at a 0-based base slot in its universe
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    pub profile: FixtureProfile,
+    pub base: u16,
+    pub calibration: Calibration,
+    pub pan: AxisLimits,
+    pub tilt: AxisLimits,
+}
+
+// Travel limits of a moving head axis, in 16-bit position units
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AxisLimits {
+    #[serde(default)]
+    pub min: u16,
+    #[serde(default = "default_axis_max")]
+    pub max: u16,
+    #[serde(default)]
+    pub invert: bool,
+}
+
+fn default_axis_max() -> u16 {
+    u16::MAX
+}
+
+impl Default for AxisLimits {
+    fn default() -> Self {
+        AxisLimits { min: 0, max: default_axis_max(), invert: false }
+    }
+}
+
+// A value of the fixture exposed to Home Assistant as its own entity
+#[derive(Debug, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+    pub default: u16,
+}
+
+#[derive(Debug, Clone)]
+pub enum ParameterKind {
+    Number { min: u16, max: u16 },
+    // The value is the index of the selected option
+    Select(Vec<String>),
+    // 0 is off, 1 is on
+    Switch,
+}
+
+impl Parameter {
+    pub fn clamp(&self, value: u16) -> u16 {
+        match &self.kind {
+            ParameterKind::Number { min, max } => value.clamp(*min, *max),
+            ParameterKind::Select(options) => value.min(options.len().saturating_sub(1) as u16),
+            ParameterKind::Switch => value.min(1),
+        }
+    }
+}
+
+// Per-light corrections so fixtures of different makes render the same color
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Calibration {
+    // Output trim applied to each emitter's level
+    #[serde(default)]
+    pub gain: HashMap<ChannelRole, f32>,
+
+    // Measured CIE xy chromaticity and relative luminance of each emitter
+    #[serde(default)]
+    pub chromaticity: HashMap<ChannelRole, (f32, f32)>,
+    #[serde(default)]
+    pub luminance: HashMap<ChannelRole, f32>,
+
+    // CIE xy of the white that an RGB white from Home Assistant should produce
+    pub white_point: Option<(f32, f32)>,
+
+    // This light's own `white_point` and `white_gain` for white extraction,
+    // for fixtures whose white emitter differs from the profile's
+    pub white_emitter: Option<(u8, u8, u8)>,
+    pub white_gain: Option<f32>,
+}
+
+impl Calibration {
+    // Measured emitters or a target white need the color solved against the
+    // emitters rather than written straight through
+    fn needs_mixing(&self) -> bool {
+        !self.chromaticity.is_empty() || !self.luminance.is_empty() || self.white_point.is_some()
+    }
+
+    fn gain(&self, role: ChannelRole) -> f32 {
+        self.gain.get(&role).copied().unwrap_or(1.0)
+    }
+
+    // Scales XYZ so that the sRGB (D65) white lands on the target white point
+    fn adapt(&self, xyz: Xyz) -> Xyz {
+        let Some((x, y)) = self.white_point else { return xyz };
+        let source = color::xy_to_xyz(0.3127, 0.3290, 1.0);
+        let target = color::xy_to_xyz(x, y, 1.0);
+        [xyz[0] * target[0] / source[0], xyz[1], xyz[2] * target[2] / source[2]]
+    }
+}
+
+impl Fixture {
+    pub fn new(profile: FixtureProfile, base: u16) -> Self {
+        Fixture {
+            profile,
+            base,
+            calibration: Calibration::default(),
+            pan: AxisLimits::default(),
+            tilt: AxisLimits::default(),
+        }
+    }
+
+    fn axis(&self, parameter: &str) -> Option<&AxisLimits> {
+        match parameter {
+            "pan" => Some(&self.pan),
+            "tilt" => Some(&self.tilt),
+            _ => None,
+        }
+    }
+
+    pub fn parameters(&self) -> Vec<Parameter> {
+        let mut parameters: Vec<Parameter> = self.profile.channels.iter()
+            .filter(|channel| !channel.role.is_fine())
+            .filter_map(|channel| {
+                let name = channel.parameter()?.to_string();
+                if channel.role == ChannelRole::Aux {
+                    return Some(Parameter { name, kind: channel.aux_kind(), default: channel.aux_default() });
+                }
+                if channel.role == ChannelRole::ColorWheel {
+                    let default = channel.slots.iter()
+                        .position(|slot| (slot.range.0..=slot.range.1).contains(&channel.default))
+                        .unwrap_or(0);
+                    let options = channel.slots.iter().map(|slot| slot.name.clone()).collect();
+                    return Some(Parameter { name, kind: ParameterKind::Select(options), default: default as u16 });
+                }
+                // Shutters start open so fixtures aren't dark until someone opens them
+                if channel.role == ChannelRole::Shutter {
+                    return Some(Parameter { name, kind: ParameterKind::Switch, default: 1 });
+                }
+                // A strobe rate of 0 leaves the channel at its default, normally open
+                if channel.role == ChannelRole::Strobe {
+                    return Some(Parameter { name, kind: ParameterKind::Number { min: 0, max: 255 }, default: 0 });
+                }
+                Some(match self.axis(&name) {
+                    Some(axis) => Parameter {
+                        name,
+                        kind: ParameterKind::Number { min: axis.min, max: axis.max },
+                        default: ((channel.default as u16) << 8).clamp(axis.min, axis.max),
+                    },
+                    None => Parameter { name, kind: ParameterKind::Number { min: 0, max: 255 }, default: channel.default as u16 },
+                })
+            })
+            .collect();
+
+        let combined_shutter = self.profile.channel(ChannelRole::Strobe).is_some_and(|channel| channel.closed.is_some());
+        if combined_shutter && !self.profile.has(ChannelRole::Shutter) {
+            parameters.push(Parameter { name: "shutter".to_string(), kind: ParameterKind::Switch, default: 1 });
+        }
+
+        parameters
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<Parameter> {
+        self.parameters().into_iter().find(|parameter| parameter.name == name)
+    }
+
+    pub fn default_parameters(&self) -> HashMap<String, u16> {
+        self.parameters().into_iter().map(|parameter| (parameter.name.to_string(), parameter.default)).collect()
+    }
+
+    // Clamps a parameter to its range, or None if the fixture doesn't have it
+    pub fn clamp_parameter(&self, parameter: &str, value: u16) -> Option<u16> {
+        self.parameter(parameter).map(|parameter| parameter.clamp(value))
+    }
+
+    // Index of the wheel slot closest to a color, compared by chromaticity
+    // only since the wheel can't dim
+    pub fn nearest_slot(&self, color: &Color) -> Option<u16> {
+        let slots = self.profile.wheel_slots()?;
+        let (r, g, b) = color.to_srgb();
+
+        let normalized = |r: u8, g: u8, b: u8| {
+            let peak = r.max(g).max(b).max(1) as f32;
+            [r as f32 / peak, g as f32 / peak, b as f32 / peak]
+        };
+        let target = normalized(r, g, b);
+        let distance = |slot: &WheelSlot| {
+            let (r, g, b) = slot.color;
+            normalized(r, g, b).iter().zip(target.iter()).map(|(a, b)| (a - b) * (a - b)).sum::<f32>()
+        };
+
+        slots.iter().enumerate()
+            .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+            .map(|(index, _)| index as u16)
+    }
+
+    // Color reported to Home Assistant for a wheel slot
+    pub fn slot_color(&self, index: u16) -> Option<Color> {
+        let (r, g, b) = self.profile.wheel_slots()?.get(index as usize)?.color;
+        Some(Color::RGB { r, g, b })
+    }
+
+    fn parameter_value(&self, channel: &ChannelDefinition, parameters: &HashMap<String, u16>) -> u8 {
+        let value = parameters.get(channel.parameter().unwrap()).copied().unwrap_or(0);
+
+        match channel.role {
+            ChannelRole::ColorWheel => {
+                return channel.slots.get(value as usize).map(WheelSlot::value).unwrap_or(channel.default);
+            }
+            ChannelRole::Shutter => return channel.scale(if value > 0 { 255 } else { 0 }),
+            ChannelRole::Aux => return match channel.kind {
+                AuxKind::Number => channel.scale(value.min(255) as u8),
+                AuxKind::Select => channel.options.get(value as usize).map(AuxOption::value).unwrap_or(channel.default),
+                AuxKind::Switch => channel.scale(if value > 0 { 255 } else { 0 }),
+            },
+            ChannelRole::Strobe => {
+                let closed = channel.closed.filter(|_| parameters.get("shutter") == Some(&0));
+                if let Some(closed) = closed {
+                    return closed;
+                }
+                if value == 0 {
+                    return channel.default;
+                }
+            }
+            _ => {}
+        }
+
+        if !channel.role.is_position() {
+            return channel.scale(value.min(255) as u8);
+        }
+
+        let inverted = self.axis(channel.parameter().unwrap()).is_some_and(|axis| axis.invert);
+        let value = if inverted { u16::MAX - value } else { value };
+        if channel.role.is_fine() {
+            (value & 0xff) as u8
+        } else {
+            channel.scale((value >> 8) as u8)
+        }
+    }
+
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        let cell_channels = self.profile.cells.iter_mut().flat_map(|cells| cells.channels.iter_mut());
+        for channel in self.profile.channels.iter_mut().chain(cell_channels) {
+            if let Some(chromaticity) = calibration.chromaticity.get(&channel.role) {
+                channel.chromaticity = Some(*chromaticity);
+            }
+            if let Some(luminance) = calibration.luminance.get(&channel.role) {
+                channel.luminance = Some(*luminance);
+            }
+        }
+        if let Some(white_emitter) = calibration.white_emitter {
+            self.profile.white_point = white_emitter;
+        }
+        if let Some(white_gain) = calibration.white_gain {
+            self.profile.white_gain = white_gain;
+        }
+        self.calibration = calibration;
+        self
+    }
+
+    // 0-based frame slot of a channel, None if the offset runs past what a
+    // slot can hold. Config validation reports anything outside the universe.
+    pub fn address(&self, channel: &ChannelDefinition) -> Option<u16> {
+        self.base.checked_add(channel.offset)
+    }
+
+    // Solves a color into levels for every visible emitter of the fixture.
+    // `scale` is the level of the brightest emitter.
+    fn mix_emitters(&self, target: Xyz, scale: u8) -> HashMap<ChannelRole, u8> {
+        // Cells repeat the same emitters, which only need solving once
+        let mut roles = Vec::new();
+        let emitters: Vec<&ChannelDefinition> = self.profile.channels.iter()
+            .filter(|channel| channel.emitter().is_some())
+            .filter(|channel| {
+                let new = !roles.contains(&channel.role);
+                roles.push(channel.role);
+                new
+            })
+            .collect();
+        let xyz: Vec<Xyz> = emitters.iter().filter_map(|channel| channel.emitter()).collect();
+
+        let target = self.calibration.adapt(target);
+        let levels = match color::mix(target, &xyz) {
+            Some(levels) => levels,
+            None => {
+                debug!("Color {:?} is outside the fixture gamut, using RGB only", target);
+                let (r, g, b) = color::xyz_to_srgb(target);
+                let scale = |value: u8| (value as u16 * scale as u16 / 255) as u8;
+                return HashMap::from([(ChannelRole::Red, scale(r)), (ChannelRole::Green, scale(g)), (ChannelRole::Blue, scale(b))]);
+            }
+        };
+
+        emitters.iter().zip(levels)
+            .map(|(channel, level)| (channel.role, (level * scale as f32).round() as u8))
+            .collect()
+    }
+
+    fn emitter_levels(&self, state: &HomeAssistantLightState) -> HashMap<ChannelRole, u8> {
+        let profile = &self.profile;
+        let tunable_white = (profile.has(ChannelRole::WarmWhite) && profile.has(ChannelRole::CoolWhite))
+            || profile.has(ChannelRole::CT);
+        let color_temp_requested = state.color_mode == Some(ColorMode::ColorTemp) || state.color.is_none();
+
+        if tunable_white && (color_temp_requested || profile.color_mode() == ColorMode::ColorTemp) {
+            // Mix warm and cool so the dominant emitter always runs at full output
+            let cool = profile.cool_share(state.color_temp.unwrap_or(profile.min_mireds()));
+            return HashMap::from([
+                (ChannelRole::WarmWhite, (255.0 * (1.0 - cool) / cool.max(1.0 - cool)).round() as u8),
+                (ChannelRole::CoolWhite, (255.0 * cool / cool.max(1.0 - cool)).round() as u8),
+                (ChannelRole::CT, (255.0 * cool).round() as u8),
+            ]);
+        }
+
+        let explicit_white = matches!(state.color, Some(Color::RGBW { .. }) | Some(Color::RGBWW { .. }))
+            && !color_temp_requested;
+        let mixing = profile.is_multi_emitter() || (self.calibration.needs_mixing() && !explicit_white);
+
+        // Everything else is brought down to RGB plus whatever white the
+        // command carried, which is folded back into RGB if the fixture has
+        // no emitter for it
+        let (mut r, mut g, mut b, mut white, mut cool_warm) = match state.color {
+            _ if color_temp_requested && state.color_temp.is_some() => {
+                let (r, g, b) = color::mireds_to_srgb(state.color_temp.unwrap());
+                (r, g, b, 0, None)
+            }
+            Some(Color::RGBWW { r, g, b, c, w }) => (r, g, b, 0, Some((c, w))),
+            Some(Color::RGBW { r, g, b, w }) => (r, g, b, w, None),
+            Some(Color::RGB { r, g, b }) => (r, g, b, 0, None),
+            Some(Color::HS { h, s }) => {
+                let (r, g, b) = color::hs_to_srgb(h, s);
+                (r, g, b, 0, None)
+            }
+            Some(Color::XY { x, y }) if mixing => {
+                return self.mix_emitters(color::xy_to_xyz(x, y, 1.0), 255);
+            }
+            Some(Color::XY { x, y }) => {
+                let (r, g, b) = color::xy_to_srgb(x, y);
+                (r, g, b, 0, None)
+            }
+            None => (255, 255, 255, 255, None),
+        };
+
+        if profile.has(ChannelRole::White) && profile.white_extraction && !explicit_white && !mixing {
+            (r, g, b, white) = profile.extract_white(r, g, b);
+        }
+
+        if !(profile.has(ChannelRole::WarmWhite) && profile.has(ChannelRole::CoolWhite))
+            && let Some((c, w)) = cool_warm.take() {
+            white = white.max(((c as u16 + w as u16) / 2) as u8);
+        }
+        if !profile.has(ChannelRole::White) && cool_warm.is_none() {
+            r = r.saturating_add(white);
+            g = g.saturating_add(white);
+            b = b.saturating_add(white);
+            white = 0;
+        }
+
+        if mixing {
+            // Keep the dimming HA encodes into the color itself
+            return self.mix_emitters(color::srgb_to_xyz(r, g, b), r.max(g).max(b));
+        }
+
+        let (cool_level, warm_level) = cool_warm.unwrap_or((0, 0));
+
+        HashMap::from([
+            (ChannelRole::Red, r),
+            (ChannelRole::Green, g),
+            (ChannelRole::Blue, b),
+            (ChannelRole::White, white),
+            (ChannelRole::WarmWhite, warm_level),
+            (ChannelRole::CoolWhite, cool_level),
+        ])
+    }
+
+    pub fn frame_values(&self, state: &HomeAssistantLightState, parameters: &HashMap<String, u16>) -> Vec<(u16, u8)> {
+        let has_intensity = self.profile.has(ChannelRole::Intensity);
+
+        if state.state == State::Off {
+            // Leave the color channels alone so the light comes back as it was,
+            // unless there is no intensity channel to turn it off with.
+            return self.profile.channels.iter()
+                .filter(|channel| match channel.role {
+                    ChannelRole::Intensity | ChannelRole::Switch | ChannelRole::UV => true,
+                    role => role.is_emitter() && !has_intensity,
+                })
+                .filter_map(|channel| Some((self.address(channel)?, channel.scale(0))))
+                .collect();
+        }
+
+        let mut levels = self.emitter_levels(state);
+
+        for (role, level) in levels.iter_mut() {
+            let gain = self.calibration.gain(*role);
+            if gain != 1.0 {
+                *level = (*level as f32 * gain).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+
+        if !has_intensity {
+            let brightness = state.brightness.unwrap_or(255) as f32 / 255.0;
+            let factor = brightness.powf(self.profile.dimming_gamma);
+            for (role, level) in levels.iter_mut() {
+                if role.is_emitter() {
+                    *level = (*level as f32 * factor).round() as u8;
+                }
+            }
+        }
+
+        let value = |channel: &ChannelDefinition| {
+            let value = match channel.role {
+                ChannelRole::Intensity => state.brightness.unwrap_or(255),
+                ChannelRole::Switch => 255,
+                _ if channel.parameter().is_some() => return self.parameter_value(channel, parameters),
+                role => match levels.get(&role) {
+                    Some(level) => *level,
+                    None => return channel.default,
+                },
+            };
+            channel.scale(value)
+        };
+        self.profile.channels.iter()
+            .filter_map(|channel| Some((self.address(channel)?, value(channel))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunable_white() -> FixtureProfile {
+        FixtureProfile::new(vec![
+            ChannelDefinition::new(ChannelRole::WarmWhite, 0),
+            ChannelDefinition::new(ChannelRole::CoolWhite, 1),
+        ])
+    }
+
+    fn rgbw() -> FixtureProfile {
+        FixtureProfile::new(vec![
+            ChannelDefinition::new(ChannelRole::Red, 0),
+            ChannelDefinition::new(ChannelRole::Green, 1),
+            ChannelDefinition::new(ChannelRole::Blue, 2),
+            ChannelDefinition::new(ChannelRole::White, 3),
+        ])
+    }
+
+    #[test]
+    fn extracts_white_common_to_red_green_and_blue() {
+        let profile = rgbw();
+
+        assert_eq!(profile.extract_white(255, 255, 255), (0, 0, 0, 255));
+        assert_eq!(profile.extract_white(200, 100, 100), (200, 0, 0, 200));
+        assert_eq!(profile.extract_white(0, 0, 255), (0, 0, 255, 0));
+        assert_eq!(profile.extract_white(0, 0, 0), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn extracts_white_relative_to_the_white_point() {
+        // A warm white emitter only covers part of the blue
+        let profile = FixtureProfile { white_point: (255, 200, 128), ..rgbw() };
+        assert_eq!(profile.extract_white(255, 200, 128), (0, 0, 0, 255));
+
+        let (r, g, b, w) = profile.extract_white(255, 255, 255);
+        assert_eq!((r, w), (0, 255));
+        assert!(g > 0 && b > g, "{} {}", g, b);
+
+        // A dimmer white emitter takes over less of the color
+        let profile = FixtureProfile { white_gain: 0.5, ..rgbw() };
+        assert_eq!(profile.extract_white(200, 100, 100), (100, 0, 0, 200));
+    }
+
+    #[test]
+    fn overrides_white_extraction_per_light() {
+        let calibration = Calibration { white_emitter: Some((255, 200, 128)), white_gain: Some(0.5), ..Calibration::default() };
+        let fixture = Fixture::new(rgbw(), 0).with_calibration(calibration);
+
+        assert_eq!((fixture.profile.white_point, fixture.profile.white_gain), ((255, 200, 128), 0.5));
+        assert!(!fixture.calibration.needs_mixing());
+    }
+
+    #[test]
+    fn rejects_white_gain_that_is_not_positive() {
+        let profile = FixtureProfile { white_gain: 0.0, ..rgbw() };
+        assert_eq!(profile.problems().len(), 1);
+    }
+
+    #[test]
+    fn rejects_cells_past_the_end_of_the_universe() {
+        let cells = |count: u16, stride: Option<u16>| CellLayout { count, start: 2, stride, channels: rgbw().channels };
+
+        let bar = FixtureProfile { cells: Some(cells(12, None)), ..FixtureProfile::new(Vec::new()) };
+        assert!(bar.problems().is_empty());
+        assert_eq!(bar.flattened().channels.last().unwrap().offset, 49);
+
+        for cells in [cells(128, None), cells(40000, None), cells(3, Some(u16::MAX))] {
+            let profile = FixtureProfile { cells: Some(cells), ..FixtureProfile::new(Vec::new()) };
+            assert_eq!(profile.problems().len(), 1);
+        }
+    }
+
+    #[test]
+    fn rejects_kelvin_ranges_that_cannot_become_mireds() {
+        assert!(tunable_white().problems().is_empty());
+
+        let inverted = FixtureProfile { warm_kelvin: 6500, cool_kelvin: 2700, ..tunable_white() };
+        assert_eq!(inverted.problems().len(), 1);
+
+        let zero = FixtureProfile { warm_kelvin: 0, ..tunable_white() };
+        assert_eq!(zero.problems().len(), 1);
+    }
+
+    #[test]
+    fn shares_color_temperature_between_warm_and_cool() {
+        let profile = FixtureProfile { warm_kelvin: 2000, cool_kelvin: 5000, ..tunable_white() };
+
+        assert_eq!((profile.min_mireds(), profile.max_mireds()), (200, 500));
+        assert_eq!(profile.cool_share(200), 1.0);
+        assert_eq!(profile.cool_share(350), 0.5);
+        // Out of range temperatures are clamped to the nearest end
+        assert_eq!(profile.cool_share(1000), 0.0);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::anyhow;
+use log::debug;
+use serde::Deserialize;
+
+use super::{parse_hex_color, ChannelDefinition, ChannelRole, FixtureProfile, WheelSlot};
+
+// Subset of the Open Fixture Library fixture format
+// (https://github.com/OpenLightingProject/open-fixture-library/blob/master/docs/fixture-format.md)
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OflFixture {
+    name: String,
+    #[serde(default)]
+    available_channels: HashMap<String, OflChannel>,
+    #[serde(default)]
+    wheels: HashMap<String, OflWheel>,
+    modes: Vec<OflMode>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OflWheel {
+    slots: Vec<OflWheelSlot>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OflWheelSlot {
+    #[serde(rename = "type")]
+    kind: String,
+    name: Option<String>,
+    #[serde(default)]
+    colors: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OflChannel {
+    #[serde(default)]
+    fine_channel_aliases: Vec<String>,
+    default_value: Option<serde_json::Value>,
+    capability: Option<OflCapability>,
+    #[serde(default)]
+    capabilities: Vec<OflCapability>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OflCapability {
+    #[serde(rename = "type")]
+    kind: String,
+    dmx_range: Option<(u8, u8)>,
+    color: Option<String>,
+    shutter_effect: Option<String>,
+    // WheelSlot capabilities select a slot (1-based, halves sit between two
+    // slots) of the wheel named after the channel unless given
+    slot_number: Option<f32>,
+    wheel: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OflMode {
+    name: String,
+    short_name: Option<String>,
+    channels: Vec<serde_json::Value>,
+}
+
+impl OflCapability {
+    fn role(&self) -> Option<ChannelRole> {
+        match self.kind.as_str() {
+            "Intensity" => Some(ChannelRole::Intensity),
+            "ColorIntensity" => match self.color.as_deref() {
+                Some("Red") => Some(ChannelRole::Red),
+                Some("Green") => Some(ChannelRole::Green),
+                Some("Blue") => Some(ChannelRole::Blue),
+                Some("White") => Some(ChannelRole::White),
+                Some("Warm White") => Some(ChannelRole::WarmWhite),
+                Some("Cold White") => Some(ChannelRole::CoolWhite),
+                Some("Amber") => Some(ChannelRole::Amber),
+                Some("Lime") => Some(ChannelRole::Lime),
+                Some("Cyan") => Some(ChannelRole::Cyan),
+                Some("UV") => Some(ChannelRole::UV),
+                _ => None,
+            },
+            "ColorTemperature" => Some(ChannelRole::CT),
+            "ShutterStrobe" | "StrobeSpeed" => Some(ChannelRole::Strobe),
+            "Pan" => Some(ChannelRole::Pan),
+            "Tilt" => Some(ChannelRole::Tilt),
+            "PanTiltSpeed" => Some(ChannelRole::PanTiltSpeed),
+            _ => None,
+        }
+    }
+}
+
+impl OflChannel {
+    fn capabilities(&self) -> impl Iterator<Item = &OflCapability> {
+        self.capability.iter().chain(self.capabilities.iter())
+    }
+
+    fn default_value(&self) -> u8 {
+        match &self.default_value {
+            Some(serde_json::Value::Number(value)) => value.as_u64().unwrap_or(0).min(255) as u8,
+            Some(serde_json::Value::String(percent)) => percent.trim_end_matches('%')
+                .parse::<f32>()
+                .map(|percent| (percent * 2.55).round().clamp(0.0, 255.0) as u8)
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    // Slots of a color wheel, telling it apart from gobo and other wheels by
+    // what its slots hold
+    fn wheel_slots(&self, name: &str, wheels: &HashMap<String, OflWheel>) -> Vec<WheelSlot> {
+        self.capabilities()
+            .filter(|capability| capability.kind == "WheelSlot")
+            .filter_map(|capability| {
+                let number = capability.slot_number.filter(|number| number.fract() == 0.0 && *number >= 1.0)? as usize;
+                let wheel = capability.wheel.as_ref().and_then(|wheel| wheel.as_str()).unwrap_or(name);
+                let slot = wheels.get(wheel)?.slots.get(number - 1)?;
+                let (name, color) = match slot.kind.as_str() {
+                    "Open" => ("Open".to_string(), (255, 255, 255)),
+                    "Color" => (
+                        slot.name.clone().unwrap_or_else(|| format!("Slot {}", number)),
+                        slot.colors.first().and_then(|color| parse_hex_color(color)).unwrap_or((255, 255, 255)),
+                    ),
+                    _ => return None,
+                };
+                Some(WheelSlot { name, range: capability.dmx_range.unwrap_or((0, 255)), color })
+            })
+            .collect()
+    }
+
+    fn to_definition(&self, name: &str, offset: u16, wheels: &HashMap<String, OflWheel>) -> Option<ChannelDefinition> {
+        let slots = self.wheel_slots(name, wheels);
+        if slots.len() > 1 {
+            let mut definition = ChannelDefinition::new(ChannelRole::ColorWheel, offset);
+            definition.default = self.default_value();
+            definition.slots = slots;
+            return Some(definition);
+        }
+
+        // Open/closed shutter steps carry the role but not the range that drives it
+        let capability = self.capabilities()
+            .find(|capability| capability.role().is_some()
+                && !matches!(capability.shutter_effect.as_deref(), Some("Open") | Some("Closed")))
+            .or_else(|| self.capabilities().find(|capability| capability.role().is_some()))?;
+        let mut definition = ChannelDefinition::new(capability.role()?, offset);
+
+        if let Some(range) = capability.dmx_range {
+            definition.range = range;
+        }
+        definition.default = self.default_value();
+
+        // Park shutters open rather than at 0, which is closed on most fixtures
+        if let Some(open) = self.capabilities().find(|capability| capability.shutter_effect.as_deref() == Some("Open")) {
+            definition.default = open.dmx_range.map(|(start, _)| start).unwrap_or(definition.default);
+        }
+        if let Some(closed) = self.capabilities().find(|capability| capability.shutter_effect.as_deref() == Some("Closed")) {
+            definition.closed = closed.dmx_range.map(|(start, _)| start);
+        }
+
+        Some(definition)
+    }
+
+    // Capabilities the definition leaves undriven, such as wheel rotation or effects
+    fn unsupported(&self, definition: &ChannelDefinition) -> Vec<String> {
+        self.capabilities()
+            .filter(|capability| capability.kind != "NoFunction")
+            .filter(|capability| !definition.covers(capability.dmx_range.unwrap_or((0, 255))))
+            .filter(|capability| !matches!(capability.shutter_effect.as_deref(), Some("Open") | Some("Closed")))
+            .map(|capability| {
+                let (start, end) = capability.dmx_range.unwrap_or((0, 255));
+                format!("{} ({}-{})", capability.kind, start, end)
+            })
+            .collect()
+    }
+}
+
+// The profile of a mode, along with the channels and capabilities it leaves undriven
+pub fn load(path: &Path, mode: Option<&str>) -> anyhow::Result<(FixtureProfile, Vec<String>)> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to open OFL fixture {}: {}", path.display(), e))?;
+    let fixture: OflFixture = serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("Unable to parse OFL fixture {}: {}", path.display(), e))?;
+
+    let selected = match mode {
+        Some(mode) => fixture.modes.iter()
+            .find(|m| m.name == mode || m.short_name.as_deref() == Some(mode))
+            .ok_or_else(|| anyhow!("OFL fixture {} has no mode {}", fixture.name, mode))?,
+        None => fixture.modes.first()
+            .ok_or_else(|| anyhow!("OFL fixture {} has no modes", fixture.name))?,
+    };
+
+    // Fine aliases, along with the channel they refine
+    let fine_channels: HashMap<&String, (&String, &OflChannel)> = fixture.available_channels.iter()
+        .flat_map(|(name, channel)| channel.fine_channel_aliases.iter().map(move |alias| (alias, (name, channel))))
+        .collect();
+
+    let mut channels = Vec::new();
+    let mut unsupported = Vec::new();
+
+    for (offset, key) in selected.channels.iter().enumerate() {
+        let key = match key {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(key) => key,
+            _ => return Err(anyhow!("OFL fixture {} mode {} uses matrix channels, which are not supported", fixture.name, selected.name)),
+        };
+
+        if let Some((name, coarse)) = fine_channels.get(key) {
+            match coarse.to_definition(name, offset as u16, &fixture.wheels).map(|definition| definition.role) {
+                Some(ChannelRole::Pan) => channels.push(ChannelDefinition::new(ChannelRole::PanFine, offset as u16)),
+                Some(ChannelRole::Tilt) => channels.push(ChannelDefinition::new(ChannelRole::TiltFine, offset as u16)),
+                _ => debug!("OFL fixture {}: skipping fine channel {}", fixture.name, key),
+            }
+            continue;
+        }
+
+        let channel = fixture.available_channels.get(key)
+            .ok_or_else(|| anyhow!("OFL fixture {} mode {} references unknown channel {}", fixture.name, selected.name, key))?;
+
+        match channel.to_definition(key, offset as u16, &fixture.wheels) {
+            Some(definition) => {
+                let capabilities = channel.unsupported(&definition);
+                if !capabilities.is_empty() {
+                    unsupported.push(format!("channel {} does not drive {}", key, capabilities.join(", ")));
+                }
+                channels.push(definition);
+            }
+            None => unsupported.push(format!("channel {} has no supported capability", key)),
+        }
+    }
+
+    Ok((FixtureProfile::new(channels), unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("profiles/ofl").join(name)
+    }
+
+    fn roles(profile: &FixtureProfile) -> Vec<(ChannelRole, u16)> {
+        profile.channels.iter().map(|channel| (channel.role, channel.offset)).collect()
+    }
+
+    #[test]
+    fn maps_capabilities_to_roles() {
+        let profile = load(&fixture("led-par-rgbw.json"), None).unwrap().0;
+
+        // Program and its speed have no role and are left out
+        assert_eq!(roles(&profile), vec![
+            (ChannelRole::Intensity, 0),
+            (ChannelRole::Red, 1),
+            (ChannelRole::Green, 2),
+            (ChannelRole::Blue, 3),
+            (ChannelRole::White, 4),
+            (ChannelRole::Strobe, 5),
+        ]);
+        assert_eq!(profile.channel(ChannelRole::Intensity).unwrap().default, 255);
+    }
+
+    #[test]
+    fn picks_modes_by_name_or_short_name() {
+        let by_short_name = load(&fixture("led-par-rgbw.json"), Some("4ch")).unwrap().0;
+        let by_name = load(&fixture("led-par-rgbw.json"), Some("4-channel")).unwrap().0;
+
+        assert_eq!(roles(&by_short_name), roles(&by_name));
+        assert_eq!(roles(&by_short_name)[0], (ChannelRole::Red, 0));
+        assert!(load(&fixture("led-par-rgbw.json"), Some("16ch")).is_err());
+    }
+
+    #[test]
+    fn maps_fine_channel_aliases() {
+        let profile = load(&fixture("spot-moving-head.json"), Some("11ch")).unwrap().0;
+
+        assert_eq!(roles(&profile)[..5], [
+            (ChannelRole::Pan, 0),
+            (ChannelRole::PanFine, 1),
+            (ChannelRole::Tilt, 2),
+            (ChannelRole::TiltFine, 3),
+            (ChannelRole::PanTiltSpeed, 4),
+        ]);
+        assert_eq!(profile.channel(ChannelRole::Pan).unwrap().default, 128);
+    }
+
+    #[test]
+    fn parks_shutters_open() {
+        let profile = load(&fixture("led-par-rgbw.json"), None).unwrap().0;
+        let strobe = profile.channel(ChannelRole::Strobe).unwrap();
+        assert_eq!((strobe.range, strobe.default, strobe.closed), ((16, 255), 8, Some(0)));
+
+        let profile = load(&fixture("spot-moving-head.json"), None).unwrap().0;
+        let shutter = profile.channel(ChannelRole::Strobe).unwrap();
+        assert_eq!((shutter.range, shutter.default, shutter.closed), ((8, 215), 4, Some(0)));
+    }
+
+    #[test]
+    fn builds_color_wheels_from_wheel_slots() {
+        let profile = load(&fixture("spot-moving-head.json"), Some("7ch")).unwrap().0;
+        let wheel = profile.channel(ChannelRole::ColorWheel).unwrap();
+
+        assert_eq!(wheel.offset, 4);
+        // The split slot between Open and Red and the rotation are left out
+        let slots: Vec<_> = wheel.slots.iter().map(|slot| (slot.name.as_str(), slot.range, slot.color)).collect();
+        assert_eq!(slots, vec![
+            ("Open", (0, 9), (255, 255, 255)),
+            ("Red", (10, 19), (255, 0, 0)),
+            ("Green", (20, 29), (0, 255, 0)),
+            ("Blue", (30, 39), (0, 0, 255)),
+            ("Yellow", (40, 49), (255, 255, 0)),
+        ]);
+        assert!(profile.is_wheel_colored());
+    }
+
+    #[test]
+    fn rejects_matrix_channels() {
+        let error = load(&fixture("pixel-bar-4.json"), Some("12ch")).unwrap_err();
+        assert!(error.to_string().contains("matrix channels"), "{}", error);
+
+        assert_eq!(load(&fixture("pixel-bar-4.json"), Some("4ch")).unwrap().0.channels.len(), 4);
+    }
+
+    #[test]
+    fn reports_unsupported_channels_and_capabilities() {
+        let (_, unsupported) = load(&fixture("led-par-rgbw.json"), None).unwrap();
+        assert_eq!(unsupported, vec![
+            "channel Program has no supported capability",
+            "channel Program Speed has no supported capability",
+        ]);
+
+        let (_, unsupported) = load(&fixture("spot-moving-head.json"), Some("11ch")).unwrap();
+        assert_eq!(unsupported, vec![
+            "channel Color Wheel does not drive WheelSlot (50-127), WheelRotation (128-255)",
+            "channel Gobo Wheel has no supported capability",
+        ]);
+    }
+}
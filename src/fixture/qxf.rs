@@ -0,0 +1,295 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::anyhow;
+use log::debug;
+use roxmltree::{Document, Node, ParsingOptions};
+
+use super::{parse_hex_color, ChannelDefinition, ChannelRole, FixtureProfile, WheelSlot};
+
+// A <Channel> definition from a QLC+ fixture definition (.qxf)
+struct QxfChannel {
+    group: Option<String>,
+    fine: bool,
+    colour: Option<String>,
+    preset: Option<String>,
+    capabilities: Vec<QxfCapability>,
+}
+
+struct QxfCapability {
+    min: u8,
+    max: u8,
+    preset: Option<String>,
+    name: String,
+    // Color of a wheel slot, `Res1` since QLC+ 4.12 and `Color` before
+    color: Option<(u8, u8, u8)>,
+}
+
+impl QxfCapability {
+    fn is_strobe(&self) -> bool {
+        self.preset.as_deref().is_some_and(|preset| preset.starts_with("Strobe")) || self.name.to_lowercase().contains("strobe")
+    }
+
+    fn is_open(&self) -> bool {
+        self.preset.as_deref() == Some("ShutterOpen") || self.name.to_lowercase().contains("open")
+    }
+
+    fn is_closed(&self) -> bool {
+        self.preset.as_deref() == Some("ShutterClose") || self.name.to_lowercase().contains("close")
+    }
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+impl QxfChannel {
+    fn parse(node: Node) -> Self {
+        let group = child(node, "Group");
+
+        QxfChannel {
+            group: group.and_then(|group| group.text()).map(|text| text.trim().to_string()),
+            fine: group.and_then(|group| group.attribute("Byte")) == Some("1"),
+            colour: child(node, "Colour").and_then(|colour| colour.text()).map(|text| text.trim().to_string()),
+            preset: node.attribute("Preset").map(|preset| preset.to_string()),
+            capabilities: node.children()
+                .filter(|child| child.has_tag_name("Capability"))
+                .map(|capability| QxfCapability {
+                    min: capability.attribute("Min").and_then(|min| min.parse().ok()).unwrap_or(0),
+                    max: capability.attribute("Max").and_then(|max| max.parse().ok()).unwrap_or(255),
+                    preset: capability.attribute("Preset").map(|preset| preset.to_string()),
+                    name: capability.text().unwrap_or_default().trim().to_string(),
+                    color: capability.attribute("Res1").or(capability.attribute("Color")).and_then(parse_hex_color),
+                })
+                .collect(),
+        }
+    }
+
+    fn is_fine(&self) -> bool {
+        self.fine || self.preset.as_deref().is_some_and(|preset| preset.ends_with("Fine"))
+    }
+
+    // Colour group capabilities that pick a color, or the open slot
+    fn wheel_slots(&self) -> Vec<WheelSlot> {
+        self.capabilities.iter()
+            .filter_map(|capability| {
+                let open = ["open", "white"].contains(&capability.name.to_lowercase().as_str());
+                let color = capability.color.or(open.then_some((255, 255, 255)))?;
+                Some(WheelSlot { name: capability.name.clone(), range: (capability.min, capability.max), color })
+            })
+            .collect()
+    }
+
+    fn role(&self) -> Option<ChannelRole> {
+        let preset = self.preset.as_deref().and_then(|preset| match preset {
+            "IntensityMasterDimmer" | "IntensityDimmer" => Some(ChannelRole::Intensity),
+            "IntensityRed" => Some(ChannelRole::Red),
+            "IntensityGreen" => Some(ChannelRole::Green),
+            "IntensityBlue" => Some(ChannelRole::Blue),
+            "IntensityWhite" => Some(ChannelRole::White),
+            "IntensityAmber" => Some(ChannelRole::Amber),
+            "IntensityLime" => Some(ChannelRole::Lime),
+            "IntensityCyan" => Some(ChannelRole::Cyan),
+            "IntensityUV" => Some(ChannelRole::UV),
+            "ColorCTOMixer" | "ColorCTBMixer" | "ColorCTCMixer" => Some(ChannelRole::CT),
+            "PositionPan" => Some(ChannelRole::Pan),
+            "PositionTilt" => Some(ChannelRole::Tilt),
+            "PositionPanFine" => Some(ChannelRole::PanFine),
+            "PositionTiltFine" => Some(ChannelRole::TiltFine),
+            "SpeedPanTiltSlowFast" | "SpeedPanTiltFastSlow" => Some(ChannelRole::PanTiltSpeed),
+            "ShutterStrobeSlowFast" | "ShutterStrobeFastSlow" => Some(ChannelRole::Strobe),
+            _ => None,
+        });
+        if preset.is_some() {
+            return preset;
+        }
+
+        match self.group.as_deref()? {
+            "Intensity" => match self.colour.as_deref() {
+                None | Some("Generic") => Some(ChannelRole::Intensity),
+                Some("Red") => Some(ChannelRole::Red),
+                Some("Green") => Some(ChannelRole::Green),
+                Some("Blue") => Some(ChannelRole::Blue),
+                Some("White") => Some(ChannelRole::White),
+                Some("Amber") => Some(ChannelRole::Amber),
+                Some("Lime") => Some(ChannelRole::Lime),
+                Some("Cyan") => Some(ChannelRole::Cyan),
+                Some("UV") => Some(ChannelRole::UV),
+                _ => None,
+            },
+            "Pan" if self.fine => Some(ChannelRole::PanFine),
+            "Pan" => Some(ChannelRole::Pan),
+            "Tilt" if self.fine => Some(ChannelRole::TiltFine),
+            "Tilt" => Some(ChannelRole::Tilt),
+            "Shutter" => Some(ChannelRole::Strobe),
+            "Colour" if self.wheel_slots().len() > 1 => Some(ChannelRole::ColorWheel),
+            _ => None,
+        }
+    }
+
+    fn to_definition(&self, offset: u16) -> Option<ChannelDefinition> {
+        let mut definition = ChannelDefinition::new(self.role()?, offset);
+
+        if definition.role == ChannelRole::ColorWheel {
+            definition.slots = self.wheel_slots();
+        }
+
+        if definition.role == ChannelRole::Strobe {
+            if let Some(strobe) = self.capabilities.iter().find(|capability| capability.is_strobe()) {
+                definition.range = (strobe.min, strobe.max);
+            }
+            // Park shutters open rather than at 0, which is closed on most fixtures
+            if let Some(open) = self.capabilities.iter().find(|capability| capability.is_open()) {
+                definition.default = open.min;
+            }
+            if let Some(closed) = self.capabilities.iter().find(|capability| capability.is_closed()) {
+                definition.closed = Some(closed.min);
+            }
+        }
+
+        Some(definition)
+    }
+
+    // Capabilities the definition leaves undriven, such as wheel rotation
+    fn unsupported(&self, definition: &ChannelDefinition) -> Vec<String> {
+        self.capabilities.iter()
+            .filter(|capability| !definition.covers((capability.min, capability.max)))
+            .filter(|capability| definition.role != ChannelRole::Strobe || !(capability.is_open() || capability.is_closed()))
+            .map(|capability| format!("{} ({}-{})", capability.name, capability.min, capability.max))
+            .collect()
+    }
+}
+
+// The profile of a mode, along with the channels and capabilities it leaves undriven
+pub fn load(path: &Path, mode: Option<&str>) -> anyhow::Result<(FixtureProfile, Vec<String>)> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Unable to open QLC+ fixture {}: {}", path.display(), e))?;
+    // QLC+ writes a <!DOCTYPE FixtureDefinition> header into every definition
+    let options = ParsingOptions { allow_dtd: true, ..ParsingOptions::default() };
+    let document = Document::parse_with_options(&contents, options)
+        .map_err(|e| anyhow!("Unable to parse QLC+ fixture {}: {}", path.display(), e))?;
+    let root = document.root_element();
+
+    let model = child(root, "Model").and_then(|model| model.text()).unwrap_or("unknown").to_string();
+
+    let channels: HashMap<&str, QxfChannel> = root.children()
+        .filter(|child| child.has_tag_name("Channel"))
+        .filter_map(|channel| Some((channel.attribute("Name")?, QxfChannel::parse(channel))))
+        .collect();
+
+    let modes: Vec<Node> = root.children().filter(|child| child.has_tag_name("Mode")).collect();
+    let selected = match mode {
+        Some(mode) => modes.iter()
+            .find(|m| m.attribute("Name") == Some(mode))
+            .ok_or_else(|| anyhow!("QLC+ fixture {} has no mode {}", model, mode))?,
+        None => modes.first()
+            .ok_or_else(|| anyhow!("QLC+ fixture {} has no modes", model))?,
+    };
+
+    let mut definitions = Vec::new();
+    let mut unsupported = Vec::new();
+
+    for mode_channel in selected.children().filter(|child| child.has_tag_name("Channel")) {
+        let offset: u16 = mode_channel.attribute("Number")
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| anyhow!("QLC+ fixture {} has a mode channel without a valid number", model))?;
+        let name = mode_channel.text().unwrap_or_default().trim();
+
+        let channel = channels.get(name)
+            .ok_or_else(|| anyhow!("QLC+ fixture {} references unknown channel {}", model, name))?;
+
+        if channel.is_fine() && !channel.role().is_some_and(|role| role.is_fine()) {
+            debug!("QLC+ fixture {}: skipping fine channel {}", model, name);
+            continue;
+        }
+
+        match channel.to_definition(offset) {
+            Some(definition) => {
+                let capabilities = channel.unsupported(&definition);
+                if !capabilities.is_empty() {
+                    unsupported.push(format!("channel {} does not drive {}", name, capabilities.join(", ")));
+                }
+                definitions.push(definition);
+            }
+            None => unsupported.push(format!("channel {} ({}) is not supported",
+                name, channel.preset.as_deref().or(channel.group.as_deref()).unwrap_or("no group"))),
+        }
+    }
+
+    Ok((FixtureProfile::new(definitions), unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("profiles/qlc").join(name)
+    }
+
+    fn roles(profile: &FixtureProfile) -> Vec<(ChannelRole, u16)> {
+        profile.channels.iter().map(|channel| (channel.role, channel.offset)).collect()
+    }
+
+    #[test]
+    fn maps_groups_and_presets_to_roles() {
+        let profile = load(&fixture("spot-moving-head.qxf"), None).unwrap().0;
+
+        // The gobo has no role and is left out
+        assert_eq!(roles(&profile), vec![
+            (ChannelRole::Pan, 0),
+            (ChannelRole::PanFine, 1),
+            (ChannelRole::Tilt, 2),
+            (ChannelRole::TiltFine, 3),
+            (ChannelRole::ColorWheel, 4),
+            (ChannelRole::Strobe, 5),
+            (ChannelRole::Intensity, 6),
+        ]);
+    }
+
+    #[test]
+    fn picks_modes_by_name() {
+        let profile = load(&fixture("spot-moving-head.qxf"), Some("Basic")).unwrap().0;
+        assert_eq!(profile.channels.len(), 5);
+        assert_eq!(profile.channel(ChannelRole::Intensity).unwrap().offset, 4);
+
+        assert!(load(&fixture("spot-moving-head.qxf"), Some("Standard")).is_err());
+    }
+
+    #[test]
+    fn builds_color_wheels_from_capabilities() {
+        let profile = load(&fixture("spot-moving-head.qxf"), None).unwrap().0;
+        let wheel = profile.channel(ChannelRole::ColorWheel).unwrap();
+
+        let slots: Vec<_> = wheel.slots.iter().map(|slot| (slot.name.as_str(), slot.range, slot.color)).collect();
+        // Split colors and rotation have no single color and are left out
+        assert_eq!(slots, vec![
+            ("Open", (0, 9), (255, 255, 255)),
+            ("Red", (10, 19), (255, 0, 0)),
+            ("Green", (20, 29), (0, 255, 0)),
+            ("Blue", (30, 39), (0, 0, 255)),
+        ]);
+    }
+
+    #[test]
+    fn parks_shutters_open() {
+        let profile = load(&fixture("spot-moving-head.qxf"), None).unwrap().0;
+        let shutter = profile.channel(ChannelRole::Strobe).unwrap();
+
+        assert_eq!(shutter.range, (16, 131));
+        assert_eq!(shutter.default, 8);
+        assert_eq!(shutter.closed, Some(0));
+    }
+
+    #[test]
+    fn reports_unsupported_channels_and_capabilities() {
+        let (_, unsupported) = load(&fixture("spot-moving-head.qxf"), None).unwrap();
+
+        // The second open range of the shutter is driven by parking it open
+        assert_eq!(unsupported, vec![
+            "channel Color Wheel does not drive Split colors (40-127), Rainbow (128-255)",
+            "channel Gobo (Gobo) is not supported",
+        ]);
+    }
+}
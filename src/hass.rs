@@ -1,6 +1,12 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use crate::config::{LightChannelMapping, LightSpecification};
+use crate::color;
+use crate::config::{GroupSpecification, LightSpecification};
+use crate::fixture::{ChannelRole, ParameterKind};
 
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -23,11 +29,11 @@ pub enum Color {
     RGBWW { r: u8, g: u8, b: u8, c: u8, w: u8 },
     RGBW { r: u8, g: u8, b: u8, w: u8 },
     RGB { r: u8, g: u8, b: u8 },
-    XY { x: u8, y: u8 },
-    HS { h: u16, s: u8 },
+    XY { x: f32, y: f32 },
+    HS { h: f32, s: f32 },
 }
 
-#[derive(Deserialize,Serialize,Debug,Clone)]
+#[derive(Deserialize,Serialize,Debug,Clone,PartialEq)]
 pub enum ColorMode {
     #[serde(rename = "rgbww")]
     RGBWW,
@@ -43,6 +49,37 @@ pub enum ColorMode {
 
     #[serde(rename = "hs")]
     HS,
+
+    #[serde(rename = "color_temp")]
+    ColorTemp,
+
+    #[serde(rename = "brightness")]
+    Brightness,
+
+    #[serde(rename = "onoff")]
+    OnOff,
+}
+
+#[derive(Deserialize,Serialize,Debug,Clone,Copy,PartialEq)]
+pub enum Flash {
+    #[serde(rename = "short")]
+    Short,
+
+    #[serde(rename = "long")]
+    Long,
+}
+
+// Flash lengths advertised to Home Assistant, in seconds
+pub const FLASH_TIME_SHORT: u64 = 2;
+pub const FLASH_TIME_LONG: u64 = 10;
+
+impl Flash {
+    pub fn duration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(match self {
+            Flash::Short => FLASH_TIME_SHORT,
+            Flash::Long => FLASH_TIME_LONG,
+        })
+    }
 }
 
 #[derive(Deserialize,Serialize, Debug,Clone)]
@@ -50,8 +87,13 @@ pub struct HomeAssistantLightState {
     pub brightness: Option<u8>,
     pub color_mode: Option<ColorMode>,
     pub color: Option<Color>,
+    // Color temperature in mireds
+    pub color_temp: Option<u16>,
     pub state: State,
     pub effect: Option<String>,
+    // Only ever part of a command, never of the light's state
+    #[serde(skip_serializing, default)]
+    pub flash: Option<Flash>,
 }   
 
 impl Default for HomeAssistantLightState {
@@ -60,31 +102,44 @@ impl Default for HomeAssistantLightState {
             brightness: None,
             color_mode: None,
             color: None,
+            color_temp: None,
             state: State::Off,
             effect: None,
+            flash: None,
+        }
+    }
+}
+
+impl Color {
+    pub fn mode(&self) -> ColorMode {
+        match self {
+            Color::RGBWW { .. } => ColorMode::RGBWW,
+            Color::RGBW { .. } => ColorMode::RGBW,
+            Color::RGB { .. } => ColorMode::RGB,
+            Color::XY { .. } => ColorMode::XY,
+            Color::HS { .. } => ColorMode::HS,
+        }
+    }
+
+    // The color as sRGB, with white added to all three primaries
+    pub fn to_srgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::RGBWW { r, g, b, c, w } => {
+                let white = ((c as u16 + w as u16) / 2) as u8;
+                (r.saturating_add(white), g.saturating_add(white), b.saturating_add(white))
+            }
+            Color::RGBW { r, g, b, w } => (r.saturating_add(w), g.saturating_add(w), b.saturating_add(w)),
+            Color::RGB { r, g, b } => (r, g, b),
+            Color::HS { h, s } => color::hs_to_srgb(h, s),
+            Color::XY { x, y } => color::xy_to_srgb(x, y),
         }
     }
 }
 
 impl HomeAssistantLightState {
     pub fn default_from_specification(spec: &LightSpecification) -> Self {
-        match &spec.mapping {
-            LightChannelMapping::RGBWDimmer(_) => HomeAssistantLightState {
-                brightness: Some(255),
-                color_mode: Some(ColorMode::RGBW),
-                color: Some(Color::RGBW { r: 255, g: 255, b: 255, w: 255 }),
-                state: State::On,
-                effect: None,
-            },
-            LightChannelMapping::RGBDimmer(_) => HomeAssistantLightState {
-                brightness: Some(255),
-                color_mode: Some(ColorMode::RGB),
-                color: Some(Color::RGB { r: 255, g: 255, b: 255 }),
-                state: State::On,
-                effect: None,
-            },
-        }
-    }   
+        spec.fixture.profile.default_state()
+    }
 
     pub fn update_with(&mut self, other: &HomeAssistantLightState) {
         if other.brightness.is_some() {
@@ -93,8 +148,18 @@ impl HomeAssistantLightState {
         if other.color_mode.is_some() {
             self.color_mode = other.color_mode.clone();
         }
-        if other.color.is_some() {
-            self.color = other.color.clone();
+        // Commands don't carry a color mode, it follows from what was sent
+        if let Some(color) = &other.color {
+            self.color = Some(color.clone());
+            if other.color_mode.is_none() {
+                self.color_mode = Some(color.mode());
+            }
+        }
+        if other.color_temp.is_some() {
+            self.color_temp = other.color_temp;
+            if other.color_mode.is_none() && other.color.is_none() {
+                self.color_mode = Some(ColorMode::ColorTemp);
+            }
         }
         if other.effect.is_some() {
             self.effect = other.effect.clone();
@@ -113,3 +178,264 @@ pub enum State {
     Off,
 }
 
+
+
+pub fn state_topic(light: &LightSpecification) -> String {
+    format!("homeassistant/dmx/{}", light.id)
+}
+
+pub fn command_topic(light: &LightSpecification) -> String {
+    format!("homeassistant/dmx/{}/set", light.id)
+}
+
+pub fn parameter_state_topic(light: &LightSpecification, parameter: &str) -> String {
+    format!("homeassistant/dmx/{}/{}", light.id, parameter)
+}
+
+pub fn parameter_command_topic(light: &LightSpecification, parameter: &str) -> String {
+    format!("homeassistant/dmx/{}/{}/set", light.id, parameter)
+}
+
+pub fn discovery_topic(light: &LightSpecification) -> String {
+    format!("homeassistant/device/dmx_{}/config", light.id)
+}
+
+// Device discovery message for a single fixture, so that its light, its
+// segment lights and their parameter entities are grouped together under the
+// fixture's device
+pub fn discovery_message(light: &LightSpecification, segments: &[&LightSpecification]) -> serde_json::Value {
+    let mut components = serde_json::Map::new();
+    for light in std::iter::once(light).chain(segments.iter().copied()) {
+        for (unique_id, component) in discovery_components(light) {
+            components.insert(unique_id, component);
+        }
+    }
+
+    json!({
+        "device": {
+            "name": light.display_name,
+            "identifiers": [format!("dmx_{}", light.id)],
+            "manufacturer": "Maris Usis",
+            "model": "OpenDMX USB2MQTT",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        },
+        "o": {
+            "name": "DMX"
+        },
+        "cmps": components,
+    })
+}
+
+// Component entries for the device discovery message, keyed by unique id
+fn discovery_components(light: &LightSpecification) -> Vec<(String, serde_json::Value)> {
+    let mut components = vec![(light.id.clone(), discovery_component(light))];
+
+    for parameter in light.fixture.parameters() {
+        let unique_id = format!("{}_{}", light.id, parameter.name);
+        let mut component = json!({
+            "unique_id": unique_id,
+            "name": format!("{} {}", light.display_name, parameter.name.replace('_', " ")),
+            "state_topic": parameter_state_topic(light, &parameter.name),
+            "command_topic": parameter_command_topic(light, &parameter.name),
+        });
+        match parameter.kind {
+            ParameterKind::Number { min, max } => {
+                component["p"] = json!("number");
+                component["min"] = json!(min);
+                component["max"] = json!(max);
+                component["step"] = json!(1);
+            }
+            ParameterKind::Select(options) => {
+                component["p"] = json!("select");
+                component["options"] = json!(options);
+            }
+            ParameterKind::Switch => {
+                component["p"] = json!("switch");
+                component["payload_on"] = json!("ON");
+                component["payload_off"] = json!("OFF");
+            }
+        }
+        components.push((unique_id, component));
+    }
+
+    components
+}
+
+fn discovery_component(light: &LightSpecification) -> serde_json::Value {
+    if light.fixture.profile.is_switch() {
+        return json!({
+            "p": "switch",
+            "unique_id": light.id,
+            "name": light.display_name,
+            "state_topic": state_topic(light),
+            "command_topic": command_topic(light),
+            "payload_on": "ON",
+            "payload_off": "OFF",
+        });
+    }
+
+    let mut component = json!({
+        "p": "light",
+        "unique_id": light.id,
+        "identifier": light.display_name,
+        "name": light.display_name,
+        "state_topic": state_topic(light),
+        "command_topic": command_topic(light),
+        "brightness": light.color_mode() != ColorMode::OnOff,
+        "supported_color_modes": light.supported_color_modes(),
+        "schema": "json",
+        "effect": true,
+        "effect_list": ["fire", "stars"],
+        // Flashing is done with the strobe channel
+        "flash": light.fixture.profile.has(ChannelRole::Strobe),
+        "flash_time_short": FLASH_TIME_SHORT,
+        "flash_time_long": FLASH_TIME_LONG,
+    });
+
+    if light.supported_color_modes().contains(&ColorMode::ColorTemp) {
+        component["min_mireds"] = json!(light.fixture.profile.min_mireds());
+        component["max_mireds"] = json!(light.fixture.profile.max_mireds());
+    }
+
+    component
+}
+
+pub fn group_state_topic(group: &GroupSpecification) -> String {
+    format!("homeassistant/dmx/{}", group.id)
+}
+
+pub fn group_command_topic(group: &GroupSpecification) -> String {
+    format!("homeassistant/dmx/{}/set", group.id)
+}
+
+pub fn group_discovery_topic(group: &GroupSpecification) -> String {
+    format!("homeassistant/device/dmx_{}/config", group.id)
+}
+
+pub fn group_discovery_message(group: &GroupSpecification) -> serde_json::Value {
+    let mut component = json!({
+        "p": "light",
+        "unique_id": group.id,
+        "name": group.display_name,
+        "state_topic": group_state_topic(group),
+        "command_topic": group_command_topic(group),
+        "brightness": group.color_mode() != ColorMode::OnOff,
+        "supported_color_modes": group.supported_color_modes(),
+        "schema": "json",
+        "flash": group.members.iter().all(|member| member.fixture.profile.has(ChannelRole::Strobe)),
+        "flash_time_short": FLASH_TIME_SHORT,
+        "flash_time_long": FLASH_TIME_LONG,
+    });
+
+    if group.supported_color_modes().contains(&ColorMode::ColorTemp) {
+        component["min_mireds"] = json!(group.min_mireds());
+        component["max_mireds"] = json!(group.max_mireds());
+    }
+
+    json!({
+        "device": {
+            "name": group.display_name,
+            "identifiers": [format!("dmx_{}", group.id)],
+            "manufacturer": "Maris Usis",
+            "model": "OpenDMX USB2MQTT",
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        },
+        "o": {
+            "name": "DMX"
+        },
+        "cmps": {
+            group.id.clone(): component,
+        },
+    })
+}
+
+// A group is on while any member is, and shows its brightest lit member
+pub fn group_state(group: &GroupSpecification, states: &HashMap<String, HomeAssistantLightState>) -> HomeAssistantLightState {
+    let members: Vec<&HomeAssistantLightState> = group.members.iter()
+        .filter_map(|member| states.get(&member.id))
+        .collect();
+    let lit: Vec<&HomeAssistantLightState> = members.iter().copied()
+        .filter(|state| state.state == State::On)
+        .collect();
+
+    let Some(shown) = lit.iter().copied()
+        .max_by_key(|state| state.brightness.unwrap_or(255))
+        .or(members.first().copied()) else {
+        return HomeAssistantLightState { color_mode: Some(group.color_mode()), ..Default::default() };
+    };
+
+    let supported = group.supported_color_modes();
+    let mut state = shown.clone();
+    state.state = if lit.is_empty() { State::Off } else { State::On };
+
+    // Members may report a mode the group doesn't advertise
+    if !state.color_mode.as_ref().is_some_and(|mode| supported.contains(mode)) {
+        let xy = state.color.as_ref()
+            .filter(|_| supported.contains(&ColorMode::XY))
+            .map(|color| {
+                let (r, g, b) = color.to_srgb();
+                let (x, y) = color::srgb_to_xy(r, g, b);
+                Color::XY { x, y }
+            });
+        state.color_mode = Some(if xy.is_some() { ColorMode::XY } else { group.color_mode() });
+        state.color = xy;
+    }
+    if !supported.contains(&ColorMode::ColorTemp) {
+        state.color_temp = None;
+    }
+
+    state
+}
+
+pub fn parse_command(light: &LightSpecification, payload: &str) -> anyhow::Result<HomeAssistantLightState> {
+    if light.fixture.profile.is_switch() {
+        let state = match payload.trim() {
+            "ON" => State::On,
+            "OFF" => State::Off,
+            other => return Err(anyhow!("Invalid switch command {} for {}", other, light.id)),
+        };
+        return Ok(HomeAssistantLightState { state, ..Default::default() });
+    }
+
+    Ok(serde_json::from_str::<HomeAssistantLightState>(payload)?)
+}
+
+pub fn state_payload(light: &LightSpecification, state: &HomeAssistantLightState) -> anyhow::Result<String> {
+    if light.fixture.profile.is_switch() {
+        return Ok(match state.state {
+            State::On => "ON",
+            State::Off => "OFF",
+        }.to_string());
+    }
+
+    Ok(serde_json::to_string(state)?)
+}
+
+pub fn parse_parameter(light: &LightSpecification, parameter: &str, payload: &str) -> anyhow::Result<u16> {
+    let kind = light.fixture.parameter(parameter)
+        .ok_or_else(|| anyhow!("Light {} has no parameter {}", light.id, parameter))?
+        .kind;
+
+    match kind {
+        ParameterKind::Select(options) => options.iter()
+            .position(|option| option == payload.trim())
+            .map(|index| index as u16)
+            .ok_or_else(|| anyhow!("Invalid option {} for {} of {}", payload, parameter, light.id)),
+        ParameterKind::Switch => match payload.trim() {
+            "ON" => Ok(1),
+            "OFF" => Ok(0),
+            other => Err(anyhow!("Invalid switch command {} for {} of {}", other, parameter, light.id)),
+        },
+        ParameterKind::Number { .. } => payload.trim().parse::<f32>()
+            .map(|value| value.round().clamp(0.0, u16::MAX as f32) as u16)
+            .map_err(|_| anyhow!("Invalid value {} for {} of {}", payload, parameter, light.id)),
+    }
+}
+
+pub fn parameter_payload(light: &LightSpecification, parameter: &str, value: u16) -> String {
+    match light.fixture.parameter(parameter).map(|parameter| parameter.kind) {
+        Some(ParameterKind::Select(options)) => options.get(value as usize).cloned().unwrap_or_default(),
+        Some(ParameterKind::Switch) => if value > 0 { "ON" } else { "OFF" }.to_string(),
+        _ => value.to_string(),
+    }
+}
@@ -107,7 +107,9 @@ impl DMXLight for RGBDimmerLight {
                 b: self.state.b
             }),
             state: if self.state.on { crate::hass::State::On } else { crate::hass::State::Off },
-            effect: None
+            color_temp: None,
+            effect: None,
+            flash: None,
         }
     }
     
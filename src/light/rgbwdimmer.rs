@@ -114,7 +114,9 @@ impl DMXLight for RGBWDimmerLight {
                 w: self.state.w,
             }),
             state: if self.state.on { crate::hass::State::On } else { crate::hass::State::Off },
-            effect: None
+            color_temp: None,
+            effect: None,
+            flash: None,
         }
     }
     
@@ -1,16 +1,16 @@
 use enttecopendmx;
 use libftd2xx::Ft232r;
-use libftd2xx::Ftdi;
-use libftd2xx::FtdiCommon;
 use paho_mqtt::DisconnectOptions;
 use paho_mqtt::Message;
 use serde::Deserialize;
 use serde_json::json;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 use core::panic;
 use std::collections::HashMap;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::hash::Hash;
 use std::iter::Map;
 use std::str::FromStr;
@@ -18,9 +18,10 @@ use std::sync::Arc;
 use std::sync::RwLock;
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use paho_mqtt as mqtt;
 use anyhow::anyhow;
+use clap::{Parser, Subcommand};
 
 use log::{debug, error, info, trace, warn};
 
@@ -28,11 +29,13 @@ mod dmx;
 use dmx::DMXDriver;
 
 mod config;
-use config::{Config, LightChannelMapping, LightSpecification};
+use config::{Config, GroupSpecification, LightSpecification, UniverseSpecification};
 
+use crate::admin::AdminCommand;
 use crate::control::ControlMessage;
 use crate::control::LightController;
-use crate::dmx::FTDIDMXController;
+use crate::dmx::{DMXController, FTDIDMXController};
+use crate::fixture::FixtureProfile;
 use crate::dmx::FTDI_DMX_Driver;
 use crate::hass::HassStatusMessage;
 use crate::hass::HomeAssistantLightState;
@@ -42,25 +45,237 @@ use crate::light::DMXLight;
 // mod light;
 mod light;
 mod hass;
+mod color;
 mod control;
+mod fixture;
+mod patch;
+mod admin;
 
 
-fn load_config() -> Config {
-    let config_contents = match fs::read_to_string("config.toml") {
-        Ok(contents) => contents,
-        Err(e) => panic!("Unable to open the config file: {:?}", e),
-    };
+const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Bridges DMX fixtures to Home Assistant over MQTT")]
+struct Args {
+    /// Config file to use
+    #[arg(long, global = true, default_value = CONFIG_PATH)]
+    config: PathBuf,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the bridge (the default)
+    Run,
+    /// List the connected FTDI and USB serial adapters and their serial numbers
+    ///
+    /// Universes are opened by serial number through the FTDI D2XX driver. An
+    /// FTDI adapter that shows up as a serial port (e.g. /dev/ttyUSB0) is bound
+    /// to the kernel's ftdi_sio driver and has to be unbound from it before it
+    /// can be used as a universe.
+    ListDevices,
+    /// Validate the config without connecting to anything
+    CheckConfig {
+        /// Rewrite lights in the old `config` format
+        #[arg(long)]
+        migrate: bool,
+    },
+    /// Set a single DMX channel directly on a universe's adapter
+    ///
+    /// The adapter is opened directly, so the bridge must not be running. Every
+    /// other channel of the universe is sent as 0, and the universe is blacked
+    /// out when the frame stops being sent after --hold.
+    Set {
+        universe: String,
+        /// DMX channel, 1-512
+        #[arg(value_parser = clap::value_parser!(u16).range(1..=512))]
+        channel: u16,
+        value: u8,
+        /// Seconds to keep sending the frame for
+        #[arg(long, default_value_t = 1.0)]
+        hold: f64,
+    },
+    /// Send an all-zero frame to every universe, or just the one given
+    ///
+    /// The adapters are opened directly, so the bridge must not be running.
+    Blackout {
+        universe: Option<String>,
+        /// Seconds to keep sending the frame for
+        #[arg(long, default_value_t = 1.0)]
+        hold: f64,
+    },
+}
+
+fn load_config(path: &Path) -> anyhow::Result<Config> {
+    let (config, migrated) = Config::load(path)?;
+    for id in migrated {
+        warn!("Light {} uses the old `config` mapping, run `dmx3 check-config --migrate` to update {}", id, path.display());
+    }
+    Ok(config)
+}
+
+fn load_profiles(config: &Config) -> anyhow::Result<HashMap<String, FixtureProfile>> {
+    let (profiles, unsupported) = config.fixture_profiles()?;
+    for note in unsupported {
+        warn!("{}", note);
+    }
+    Ok(profiles)
+}
+
+fn list_devices(config_path: &Path) -> anyhow::Result<()> {
+    // Without the D2XX library there may still be serial ports to show
+    let devices = libftd2xx::list_devices().unwrap_or_else(|e| {
+        eprintln!("Unable to list FTDI devices: {:?}", e);
+        Vec::new()
+    });
+    let ports = dmx::serial_ports();
+    if devices.is_empty() && ports.is_empty() {
+        println!("No FTDI devices or USB serial ports found");
+        return Ok(());
+    }
+
+    // Point out the adapters the config already uses, if it can be read
+    let universes = Config::load(config_path).map(|(config, _)| config.universes).unwrap_or_default();
+    let universe = |serial: &str| universes.iter()
+        .find(|universe| universe.serial == serial)
+        .map(|universe| format!(" (universe {})", universe.id))
+        .unwrap_or_default();
+    for device in devices {
+        println!("{}\t{}\t{:?}\t{:04x}:{:04x}{}{}",
+            device.serial_number, device.description, device.device_type, device.vendor_id, device.product_id,
+            if device.port_open { " in use" } else { "" }, universe(&device.serial_number));
+    }
+    for port in ports {
+        let serial = port.serial_number.as_deref().unwrap_or("no serial");
+        println!("{}\t{}\t{}\t{:04x}:{:04x}{}{}",
+            serial, port.path.display(), port.driver.as_deref().unwrap_or("no driver"), port.vendor_id, port.product_id,
+            if port.needs_unbinding() { " bound to ftdi_sio, unbind it to use as a universe" } else { "" },
+            port.serial_number.as_deref().map(universe).unwrap_or_default());
+    }
+    Ok(())
+}
+
+// Writes a frame straight to the adapters of the given universes and keeps it
+// going for `hold`, for testing fixtures and scripts while the bridge is stopped.
+// Stopping blacks the universes out and closes the adapters.
+async fn send_frame(universes: &[UniverseSpecification], values: Vec<(u16, u8)>, hold: f64) -> anyhow::Result<()> {
+    let mut controllers = Vec::new();
+    for universe in universes {
+        let mut controller = open_universe(universe)
+            .map_err(|e| anyhow!("{}, is the bridge running?", e))?;
+        controller.update_many(values.clone()).await?;
+        controller.start()?;
+        controllers.push(controller);
+    }
+
+    tokio::time::sleep(Duration::from_secs_f64(hold.max(0.0))).await;
+    for controller in controllers.iter_mut() {
+        controller.stop().await?;
+    }
+    Ok(())
+}
+
+fn open_universe(universe: &UniverseSpecification) -> anyhow::Result<FTDIDMXController> {
+    let adapter = Ft232r::with_serial_number(&universe.serial)
+        .map_err(|e| anyhow!("Unable to open adapter {} of universe {}: {:?}", universe.serial, universe.id, e))?;
+    Ok(FTDIDMXController::new(FTDI_DMX_Driver::new(adapter)))
+}
+
+fn config_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn find_universes(config: &Config, universe: Option<&str>) -> anyhow::Result<Vec<UniverseSpecification>> {
+    match universe {
+        Some(id) => config.universes.iter()
+            .find(|universe| universe.id == id)
+            .map(|universe| vec![universe.clone()])
+            .ok_or_else(|| anyhow!("Universe {} is not configured", id)),
+        None => Ok(config.universes.clone()),
+    }
+}
+
+// Checks the config as it would be loaded, without opening any DMX or MQTT
+// connection. Prints every problem found and returns whether there were none.
+fn check_config(path: &Path, migrate: bool) -> bool {
+    if migrate {
+        match Config::migrate_file(path) {
+            Ok(migrated) if migrated.is_empty() => println!("No lights in {} need migrating", path.display()),
+            Ok(migrated) => println!("Migrated lights {} in {}", migrated.join(", "), path.display()),
+            Err(e) => {
+                eprintln!("{}", e);
+                return false;
+            }
+        }
+    }
 
-    let config: Config = match toml::from_str(&config_contents) {
-        Ok(data) => data,
-        Err(e) => panic!("Unable to parse the config file: {:?}", e),
+    let (config, migrated) = match Config::load(path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", e);
+            return false;
+        }
     };
+    for id in migrated {
+        println!("Warning: light {} uses the old `config` mapping, run with --migrate to update it", id);
+    }
 
-    return config;
+    let checked = config.fixture_profiles().and_then(|(profiles, unsupported)| {
+        for note in unsupported {
+            println!("Warning: {}", note);
+        }
+        let lights = config::resolve_lights(&config.lights, &profiles, &config.universes, &config.patch_table()?)?;
+        let groups = config.group_specifications(&lights)?;
+        Ok((profiles.len(), lights.len(), groups.len()))
+    });
+    match checked {
+        Ok((profiles, lights, groups)) => {
+            println!("{} is valid: {} profiles, {} lights, {} groups and zones", path.display(), profiles, lights, groups);
+            true
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    pretty_env_logger::init();
+
+    // let mut clog = colog::default_builder();
+    // clog.filter(None, log::LevelFilter::Debug);
+    // clog.init();
+
+    match args.command.unwrap_or(Command::Run) {
+        Command::Run => run(&args.config).await,
+        Command::ListDevices => list_devices(&args.config),
+        Command::CheckConfig { migrate } => std::process::exit(if check_config(&args.config, migrate) { 0 } else { 1 }),
+        Command::Set { universe, channel, value, hold } => {
+            let universes = find_universes(&load_config(&args.config)?, Some(&universe))?;
+            send_frame(&universes, vec![(channel - 1, value)], hold).await
+        }
+        Command::Blackout { universe, hold } => {
+            let universes = find_universes(&load_config(&args.config)?, universe.as_deref())?;
+            send_frame(&universes, Vec::new(), hold).await
+        }
+    }
+}
+
+// What the bridge runs with, replaced together on reloads and admin commands
+struct Runtime {
+    config: Config,
+    profiles: HashMap<String, FixtureProfile>,
+    lights: Vec<LightSpecification>,
+    groups: HashMap<String, GroupSpecification>,
+}
+
+async fn run(config_path: &Path) -> anyhow::Result<()> {
     // Create a channel for shutdown signal
     let (shutdown_tx, mut shutdown_rx) = broadcast::channel(4);
 
@@ -71,28 +286,30 @@ async fn main() -> anyhow::Result<()> {
         let _ = shutdown_tx_clone.send(());
     })?;
 
-    pretty_env_logger::init();
-
-    // let mut clog = colog::default_builder();
-    // clog.filter(None, log::LevelFilter::Debug);
-    // clog.init();
+    // SIGHUP reloads the config, as does saving the file
+    let (reload_tx, mut reload_rx) = broadcast::channel(4);
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            let _ = reload_tx.send(());
+        }
+    });
+    let mut modified = config_modified(config_path);
 
-    let config = load_config();
+    let config = load_config(config_path)?;
     debug!("Loaded config: {:?}", config);
 
-    // Open DMX interface
-    let mut ft = Ftdi::new()?;
-    let info = ft.device_info()?;
-    println!("Device information: {:?}", info);
-    ft.close()?;
+    let mut controller = LightController::new();
 
-    let ftdi_driver = FTDI_DMX_Driver::new(Ft232r::with_serial_number("AB0N3G14")?);
-    let mut dmx = FTDIDMXController::new(ftdi_driver);
+    for universe in config.universes.iter() {
+        controller.add_universe(&universe.id, open_universe(universe)?).await;
+        info!("Opened DMX universe {} on adapter {}", universe.id, universe.serial);
+    }
 
-    let mut controller = LightController::new();
-    controller.add_universe("dmx1", dmx).await;
+    let patch = config.patch_table()?;
+    controller.set_patch(patch.clone()).await;
 
-    let cli = mqtt::AsyncClient::new("mqtt://10.1.1.21:1883")?;
+    let cli = mqtt::AsyncClient::new(config.mqtt.server_uri())?;
 
     let mut builder = mqtt::ConnectOptionsBuilder::new();
 
@@ -104,52 +321,32 @@ async fn main() -> anyhow::Result<()> {
     let response = cli.connect(builder.finalize()).await?;
     info!("Connected to MQTT broker");
 
-    let mut config_message = json!({
-        "device": {
-            "name": "DMX Controller",
-            "identifiers": ["dmx_controller"],
-            "manufacturer": "Maris Usis",
-            "model": "OpenDMX USB2MQTT",
-            "sw_version": env!("CARGO_PKG_VERSION"),
-        },
-        "o": {
-            "name": "DMX"
-        },
-        "cmps": {}
-    });
+    let profiles = load_profiles(&config)?;
+    let lights = config::resolve_lights(&config.lights, &profiles, &config.universes, &patch)?;
+    controller.add_lights(lights.clone()).await?;
 
-    controller.add_lights(config.lights.clone()).await?;
+    let mut light_specifications: HashMap<String, LightSpecification> = lights.iter()
+        .map(|light| (light.id.clone(), light.clone()))
+        .collect();
 
     // Add configured lights to the system
-    for light in config.lights.iter() { 
-
-        config_message["cmps"].as_object_mut().unwrap().insert(
-            light.id.clone(),
-            json!({
-                "p": "light",
-                "unique_id": light.id,
-                "identifier": light.display_name,
-                "name": light.display_name,
-                "state_topic": format!("homeassistant/dmx/{}", light.id),
-                "command_topic": format!("homeassistant/dmx/{}/set", light.id),
-                "brightness": true,
-                "supported_color_modes": [match light.color_mode() {
-                    Some(color_mode) => color_mode,
-                    None => panic!("Light {} does not have a valid color mode", light.id),
-                }],
-                "schema": "json",
-                "effect": true,
-                "effect_list": ["fire", "stars"]
-            })
-        );
+    for light in lights.iter() { 
+        announce_light(&cli, light, &lights).await?;
+    }
 
-        
-        cli.subscribe(format!("homeassistant/dmx/{}/set", light.id), 1).await?;
-        
-        info!("Subscribed to light: {} with topic homeassistant/dmx/{}", light.id, light.id);
+    let groups: HashMap<String, GroupSpecification> = config.group_specifications(&lights)?.into_iter()
+        .map(|group| (group.id.clone(), group))
+        .collect();
+
+    for group in groups.values() {
+        announce_group(&cli, group).await?;
     }
 
-    cli.publish(Message::new("homeassistant/device/dmx_controller/config", config_message.to_string(), 1)).await?;
+    let mut runtime = Runtime { config, profiles, lights, groups };
+
+    if runtime.config.mqtt.admin {
+        cli.subscribe(admin::COMMAND_TOPIC, 1).await?;
+    }
 
     // for (light_id, light) in dmx_lights.iter() {
         
@@ -170,10 +367,70 @@ async fn main() -> anyhow::Result<()> {
         if let Ok(Some(message)) = receiver.recv_timeout(Duration::from_secs(1)) {
 
 
-            if message.topic().starts_with("homeassistant/dmx/") {
+            let topic: Vec<&str> = message.topic().split('/').collect();
+            if message.topic() == admin::COMMAND_TOPIC && runtime.config.mqtt.admin {
+                let status = match apply_admin_command(&message.payload_str(), config_path, &mut runtime, &mut controller, &cli).await {
+                    Ok(summary) => {
+                        info!("{}", summary);
+                        json!({ "ok": true, "message": summary })
+                    }
+                    Err(e) => {
+                        error!("Admin command failed: {:?}", e);
+                        json!({ "ok": false, "error": e.to_string() })
+                    }
+                };
+                light_specifications = runtime.lights.iter()
+                    .map(|light| (light.id.clone(), light.clone()))
+                    .collect();
+                cli.publish(Message::new(admin::STATUS_TOPIC, status.to_string(), 1)).await?;
+                // Already applied, so no reload for our own write
+                modified = config_modified(config_path);
+            } else if let ["homeassistant", "dmx", light_id, parameter, "set"] = topic[..] {
+                let Some(light) = light_specifications.get(light_id) else {
+                    error!("Light with ID {} not found", light_id);
+                    continue;
+                };
+                let value = match hass::parse_parameter(light, parameter, &message.payload_str()) {
+                    Ok(value) => value,
+                    Err(e) => {
+                        error!("Ignoring {} command: {:?}", parameter, e);
+                        continue;
+                    }
+                };
+
+                controller.update_parameter(light_id, parameter, value).await?;
+            } else if message.topic().starts_with("homeassistant/dmx/") {
                 let light_id = message.topic().split('/').nth(2).unwrap();
+
+                // Group commands go out to every member in one go
+                if let Some(group) = runtime.groups.get(light_id) {
+                    info!("Received message for group {}: {:?}", group.id, message);
+                    let command = match serde_json::from_str::<HomeAssistantLightState>(&message.payload_str()) {
+                        Ok(command) => command,
+                        Err(e) => {
+                            error!("Ignoring command for group {}: {:?}", group.id, e);
+                            continue;
+                        }
+                    };
+                    for member in group.members.iter() {
+                        controller.update_light_state(&member.id, command.clone()).await?;
+                    }
+                    continue;
+                }
+
                 info!("Received message for light {}: {:?}", light_id, message);
-                let hass_message = serde_json::from_str::<HomeAssistantLightState>(&message.payload_str())?;
+                let Some(light) = light_specifications.get(light_id) else {
+                    error!("Light with ID {} not found", light_id);
+                    continue;
+                };
+                // A malformed command must not take the bridge down
+                let hass_message = match hass::parse_command(light, &message.payload_str()) {
+                    Ok(hass_message) => hass_message,
+                    Err(e) => {
+                        error!("Ignoring command for light {}: {:?}", light_id, e);
+                        continue;
+                    }
+                };
 
                 controller.update_light_state(light_id, hass_message.clone()).await?;
 
@@ -188,8 +445,8 @@ async fn main() -> anyhow::Result<()> {
                 //         anyhow!("Failed to update DMX values")
                 //     })?;
 
-                let topic = format!("homeassistant/dmx/{}", light_id);
-                let payload = serde_json::to_string(&state)
+                let topic = hass::state_topic(light);
+                let payload = hass::state_payload(light, &state)
                     .map_err(|e| {
                         error!("Failed to serialize light state: {:?}", e);
                         anyhow!("Failed to serialize light state")
@@ -199,9 +456,11 @@ async fn main() -> anyhow::Result<()> {
             }
         }
 
-        for (light_id, light) in controller.get_all_hass_states().await.iter() {
-            let topic = format!("homeassistant/dmx/{}", light_id);
-            let payload = serde_json::to_string(light)
+        let states = controller.get_all_hass_states().await;
+        for (light_id, state) in states.iter() {
+            let Some(light) = light_specifications.get(light_id) else { continue };
+            let topic = hass::state_topic(light);
+            let payload = hass::state_payload(light, state)
                 .map_err(|e| {
                     error!("Failed to serialize light state: {:?}", e);
                     anyhow!("Failed to serialize light state")
@@ -210,6 +469,18 @@ async fn main() -> anyhow::Result<()> {
             cli.publish(Message::new(topic, payload, 1)).await?;
         }
 
+        for group in runtime.groups.values() {
+            let payload = serde_json::to_string(&hass::group_state(group, &states))?;
+            cli.publish(Message::new(hass::group_state_topic(group), payload, 1)).await?;
+        }
+
+        for (light_id, parameters) in controller.get_all_parameters().await.iter() {
+            let Some(light) = light_specifications.get(light_id) else { continue };
+            for (parameter, value) in parameters.iter() {
+                cli.publish(Message::new(hass::parameter_state_topic(light, parameter), hass::parameter_payload(light, parameter, *value), 1)).await?;
+            }
+        }
+
         // for (light_id, light) in dmx_lights.iter_mut() {
         //     cli.publish(Message::new(
         //         format!("homeassistant/dmx/{}", light_id),
@@ -218,6 +489,19 @@ async fn main() -> anyhow::Result<()> {
         //     )).await?;
         // }
 
+        // A config that doesn't load is reported and the running one kept
+        let changed = config_modified(config_path);
+        if reload_rx.try_recv().is_ok() || changed != modified {
+            modified = changed;
+            match reload_config(config_path, &mut runtime, &mut controller, &cli).await {
+                Ok(summary) => info!("{}", summary),
+                Err(e) => error!("Not reloading {}: {:?}", config_path.display(), e),
+            }
+            light_specifications = runtime.lights.iter()
+                .map(|light| (light.id.clone(), light.clone()))
+                .collect();
+        }
+
         if shutdown_rx.try_recv().is_ok() {
             debug!("Exiting receive loop");
             break;
@@ -237,3 +521,212 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+// Announces a light to Home Assistant and subscribes to its command topics
+async fn announce_light(cli: &mqtt::AsyncClient, light: &LightSpecification, lights: &[LightSpecification]) -> anyhow::Result<()> {
+    // Segments are announced as part of their fixture's device
+    if light.parent.is_none() {
+        let segments: Vec<&LightSpecification> = lights.iter()
+            .filter(|segment| segment.parent.as_ref() == Some(&light.id))
+            .collect();
+        let message = hass::discovery_message(light, &segments);
+        cli.publish(Message::new(hass::discovery_topic(light), message.to_string(), 1)).await?;
+    }
+
+    cli.subscribe(hass::command_topic(light), 1).await?;
+    for parameter in light.fixture.parameters() {
+        cli.subscribe(hass::parameter_command_topic(light, &parameter.name), 1).await?;
+    }
+
+    info!("Subscribed to light: {} with topic {}", light.id, hass::command_topic(light));
+    Ok(())
+}
+
+// Removes a light from Home Assistant and stops listening to its command topics
+async fn withdraw_light(cli: &mqtt::AsyncClient, light: &LightSpecification) -> anyhow::Result<()> {
+    if light.parent.is_none() {
+        cli.publish(Message::new(hass::discovery_topic(light), "", 1)).await?;
+    }
+
+    cli.unsubscribe(hass::command_topic(light)).await?;
+    for parameter in light.fixture.parameters() {
+        cli.unsubscribe(hass::parameter_command_topic(light, &parameter.name)).await?;
+    }
+    Ok(())
+}
+
+async fn announce_group(cli: &mqtt::AsyncClient, group: &GroupSpecification) -> anyhow::Result<()> {
+    cli.publish(Message::new(hass::group_discovery_topic(group), hass::group_discovery_message(group).to_string(), 1)).await?;
+    cli.subscribe(hass::group_command_topic(group), 1).await?;
+    info!("Subscribed to group: {} with topic {}", group.id, hass::group_command_topic(group));
+    Ok(())
+}
+
+// Adds, edits or removes a light while running. The new set of lights is
+// resolved and checked before anything is touched, then written to the config
+// file, the controller and Home Assistant.
+async fn apply_admin_command(
+    payload: &str,
+    config_path: &Path,
+    runtime: &mut Runtime,
+    controller: &mut LightController,
+    cli: &mqtt::AsyncClient,
+) -> anyhow::Result<String> {
+    let command = AdminCommand::parse(payload)?;
+    let definition = command.definition()?;
+    let id = match (&command, &definition) {
+        (AdminCommand::Remove { id }, _) => id.clone(),
+        (_, Some(definition)) => definition.id.clone(),
+        _ => unreachable!(),
+    };
+
+    let existing = runtime.config.lights.iter().position(|light| light.id == id);
+    match (&command, existing) {
+        (AdminCommand::Add { .. }, Some(_)) => return Err(anyhow!("Light {} already exists", id)),
+        (AdminCommand::Edit { .. } | AdminCommand::Remove { .. }, None) => return Err(anyhow!("Light {} not found", id)),
+        _ => {}
+    }
+
+    let mut definitions = runtime.config.lights.clone();
+    match (existing, definition) {
+        (Some(index), Some(definition)) => definitions[index] = definition,
+        (None, Some(definition)) => definitions.push(definition),
+        (Some(index), None) => { definitions.remove(index); }
+        (None, None) => unreachable!(),
+    }
+    let new_lights = config::resolve_lights(&definitions, &runtime.profiles, &runtime.config.universes, &runtime.config.patch_table()?)?;
+    let new_groups = runtime.config.group_specifications(&new_lights)?;
+
+    let light = match &command {
+        AdminCommand::Add { light } | AdminCommand::Edit { light } => Some(light),
+        AdminCommand::Remove { .. } => None,
+    };
+    admin::persist(config_path, &id, light)?;
+
+    // Swapped in the way a reload does, so an edited light that keeps its
+    // profile keeps its state and channels it no longer uses go dark
+    controller.set_lights(new_lights.clone()).await;
+    controller.redraw().await?;
+
+    for light in runtime.lights.iter().filter(|light| light.id == id || light.parent.as_ref() == Some(&id)) {
+        withdraw_light(cli, light).await?;
+    }
+    for light in new_lights.iter().filter(|light| light.id == id || light.parent.as_ref() == Some(&id)) {
+        announce_light(cli, light, &new_lights).await?;
+    }
+    // Group color modes follow their members
+    for group in new_groups.iter() {
+        announce_group(cli, group).await?;
+    }
+
+    runtime.config.lights = definitions;
+    runtime.lights = new_lights;
+    runtime.groups = new_groups.into_iter().map(|group| (group.id.clone(), group)).collect();
+
+    Ok(match command {
+        AdminCommand::Add { .. } => format!("Added light {}", id),
+        AdminCommand::Edit { .. } => format!("Updated light {}", id),
+        AdminCommand::Remove { .. } => format!("Removed light {}", id),
+    })
+}
+
+// Applies the config file as it is now. It is loaded and checked in full
+// before anything changes. Lights that are unchanged keep their state and only
+// fixtures that changed are announced to Home Assistant again.
+async fn reload_config(
+    config_path: &Path,
+    runtime: &mut Runtime,
+    controller: &mut LightController,
+    cli: &mqtt::AsyncClient,
+) -> anyhow::Result<String> {
+    let new_config = load_config(config_path)?;
+    let new_profiles = load_profiles(&new_config)?;
+    let new_patch = new_config.patch_table()?;
+    let new_lights = config::resolve_lights(&new_config.lights, &new_profiles, &new_config.universes, &new_patch)?;
+    let new_groups = new_config.group_specifications(&new_lights)?;
+
+    let connection = |mqtt: &config::MQTTConfig| (mqtt.host.clone(), mqtt.username.clone(), mqtt.password.clone());
+    if connection(&new_config.mqtt) != connection(&runtime.config.mqtt) {
+        warn!("MQTT connection settings changed, restart to apply them");
+    }
+
+    // An adapter that fails to open leaves its universe dark rather than
+    // holding back the rest of the config
+    for universe in runtime.config.universes.iter().filter(|universe| !new_config.universes.contains(universe)) {
+        match controller.remove_universe(&universe.id).await {
+            Ok(()) => info!("Closed DMX universe {}", universe.id),
+            Err(e) => warn!("{:?}", e),
+        }
+    }
+    for universe in new_config.universes.iter().filter(|universe| !runtime.config.universes.contains(universe)) {
+        match open_universe(universe) {
+            Ok(dmx) => {
+                controller.add_universe(&universe.id, dmx).await;
+                info!("Opened DMX universe {} on adapter {}", universe.id, universe.serial);
+            }
+            Err(e) => error!("{:?}", e),
+        }
+    }
+
+    controller.set_patch(new_patch).await;
+    controller.set_lights(new_lights.clone()).await;
+    controller.redraw().await?;
+
+    // Fixtures are compared together with their segments
+    let fixtures = |lights: &[LightSpecification]| {
+        let mut fixtures: HashMap<String, Vec<LightSpecification>> = HashMap::new();
+        for light in lights {
+            fixtures.entry(light.parent.clone().unwrap_or(light.id.clone())).or_default().push(light.clone());
+        }
+        fixtures
+    };
+    let (old_fixtures, new_fixtures) = (fixtures(&runtime.lights), fixtures(&new_lights));
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for (id, old) in old_fixtures.iter() {
+        match new_fixtures.get(id) {
+            Some(new) if new == old => continue,
+            Some(_) => changed += 1,
+            None => removed += 1,
+        }
+        for light in old {
+            withdraw_light(cli, light).await?;
+        }
+    }
+    for (id, new) in new_fixtures.iter() {
+        match old_fixtures.get(id) {
+            Some(old) if old == new => continue,
+            Some(_) => {}
+            None => added += 1,
+        }
+        for light in new {
+            announce_light(cli, light, &new_lights).await?;
+        }
+    }
+
+    for group in runtime.groups.values().filter(|group| !new_groups.iter().any(|new| new.id == group.id)) {
+        cli.publish(Message::new(hass::group_discovery_topic(group), "", 1)).await?;
+    }
+    for group in new_groups.iter() {
+        announce_group(cli, group).await?;
+    }
+
+    if new_config.mqtt.admin != runtime.config.mqtt.admin {
+        if new_config.mqtt.admin {
+            cli.subscribe(admin::COMMAND_TOPIC, 1).await?;
+            info!("Listening for admin commands on {}", admin::COMMAND_TOPIC);
+        } else {
+            cli.unsubscribe(admin::COMMAND_TOPIC).await?;
+            info!("Stopped listening for admin commands");
+        }
+    }
+
+    *runtime = Runtime {
+        config: new_config,
+        profiles: new_profiles,
+        lights: new_lights,
+        groups: new_groups.into_iter().map(|group| (group.id.clone(), group)).collect(),
+    };
+
+    Ok(format!("Reloaded {}: {} fixtures added, {} removed, {} changed", config_path.display(), added, removed, changed))
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+// A 1-based DMX address (1-512), like a light's start address
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PatchAddress {
+    pub universe: String,
+    pub address: u16,
+}
+
+impl PatchAddress {
+    // The 0-based frame slot `i` channels on, if it is inside the universe
+    fn slot(&self, i: u16) -> Option<u16> {
+        self.address.checked_sub(1)?.checked_add(i).filter(|slot| *slot < 512)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatchEntry {
+    pub from: PatchAddress,
+    pub to: PatchAddress,
+
+    // Number of consecutive channels patched by this entry, starting at `from`/`to`
+    #[serde(default = "default_count")]
+    pub count: u16,
+
+    #[serde(default)]
+    pub invert: bool,
+
+    #[serde(default)]
+    pub offset: i16,
+}
+
+fn default_count() -> u16 {
+    1
+}
+
+impl PatchEntry {
+    // Pairs of 0-based logical and physical slots patched by this entry,
+    // leaving out any that fall outside either universe
+    pub fn slots(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        (0..self.count).filter_map(|i| Some((self.from.slot(i)?, self.to.slot(i)?)))
+    }
+}
+
+impl std::fmt::Display for PatchAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.universe, self.address)
+    }
+}
+
+// Slots one entry shares with each earlier entry. Both cover consecutive
+// channels, so what they share is a single run.
+#[derive(Default)]
+struct Runs<'a>(Vec<(&'a PatchAddress, Run)>);
+
+struct Run(u16, u16);
+
+impl<'a> Runs<'a> {
+    fn add(&mut self, other: &'a PatchAddress, slot: u16) {
+        match self.0.iter_mut().find(|(run, _)| *run == other) {
+            Some((_, run)) => run.1 = slot,
+            None => self.0.push((other, Run(slot, slot))),
+        }
+    }
+}
+
+// Shown as 1-based addresses
+impl std::fmt::Display for Run {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Run(first, last) if first == last => write!(f, "{} is", first + 1),
+            Run(first, last) => write!(f, "{}-{} are", first + 1, last + 1),
+        }
+    }
+}
+
+// Mistakes in the patch that would otherwise silently drop or overwrite
+// channels: unknown universes, addresses outside 1-512, and more than one
+// logical channel on the same physical slot
+pub fn problems(entries: &[PatchEntry], universes: &[&str]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut patched: HashMap<(&str, u16), &PatchAddress> = HashMap::new();
+    let mut targets: HashMap<(&str, u16), &PatchAddress> = HashMap::new();
+
+    for entry in entries {
+        let name = format!("Patch {} -> {}", entry.from, entry.to);
+        if entry.count == 0 {
+            problems.push(format!("{}: count must be at least 1", name));
+        }
+        for (side, address) in [("from", &entry.from), ("to", &entry.to)] {
+            if !universes.contains(&address.universe.as_str()) {
+                problems.push(format!("{}: {} universe {} is not configured", name, side, address.universe));
+            }
+            if address.address == 0 || address.address as u32 + entry.count.max(1) as u32 - 1 > 512 {
+                problems.push(format!("{}: {} address {} with count {} is outside 1-512", name, side, address.address, entry.count));
+            }
+        }
+
+        let mut repatched = Runs::default();
+        let mut shared = Runs::default();
+        for (from, to) in entry.slots() {
+            if let Some(other) = patched.insert((&entry.from.universe, from), &entry.from) {
+                repatched.add(other, from);
+            }
+            if let Some(other) = targets.insert((&entry.to.universe, to), &entry.from) {
+                shared.add(other, to);
+            }
+        }
+        for (other, run) in repatched.0 {
+            problems.push(format!("{}: {}:{} also patched by the entry from {}", name, entry.from.universe, run, other));
+        }
+        for (other, run) in shared.0 {
+            problems.push(format!("{}: {}:{} also the target of the entry from {}", name, entry.to.universe, run, other));
+        }
+    }
+
+    problems
+}
+
+#[derive(Debug, Clone)]
+struct PatchTarget {
+    universe: String,
+    // 0-based frame slot
+    address: u16,
+    invert: bool,
+    offset: i16,
+}
+
+impl PatchTarget {
+    fn value(&self, value: u8) -> u8 {
+        let value = if self.invert { 255 - value } else { value };
+        (value as i16 + self.offset).clamp(0, 255) as u8
+    }
+}
+
+// Maps logical (universe, slot) pairs produced by lights to the physical
+// universe and slot they are written to. Unpatched channels pass through.
+#[derive(Debug, Clone, Default)]
+pub struct PatchTable {
+    targets: HashMap<(String, u16), PatchTarget>,
+}
+
+impl PatchTable {
+    pub fn new(entries: &[PatchEntry]) -> Self {
+        let mut targets = HashMap::new();
+
+        for entry in entries {
+            for (from, to) in entry.slots() {
+                targets.insert(
+                    (entry.from.universe.clone(), from),
+                    PatchTarget {
+                        universe: entry.to.universe.clone(),
+                        address: to,
+                        invert: entry.invert,
+                        offset: entry.offset,
+                    },
+                );
+            }
+        }
+
+        PatchTable { targets }
+    }
+
+    // Physical universe and slot a logical slot is written to
+    pub fn target<'a>(&'a self, universe: &'a str, slot: u16) -> (&'a str, u16) {
+        match self.targets.get(&(universe.to_string(), slot)) {
+            Some(target) => (&target.universe, target.address),
+            None => (universe, slot),
+        }
+    }
+
+    pub fn apply(&self, universe: &str, values: Vec<(u16, u8)>) -> HashMap<String, Vec<(u16, u8)>> {
+        let mut physical: HashMap<String, Vec<(u16, u8)>> = HashMap::new();
+
+        for (address, value) in values {
+            match self.targets.get(&(universe.to_string(), address)) {
+                Some(target) => physical
+                    .entry(target.universe.clone())
+                    .or_default()
+                    .push((target.address, target.value(value))),
+                None => physical
+                    .entry(universe.to_string())
+                    .or_default()
+                    .push((address, value)),
+            }
+        }
+
+        physical
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(from: (&str, u16), to: (&str, u16), count: u16) -> PatchEntry {
+        PatchEntry {
+            from: PatchAddress { universe: from.0.to_string(), address: from.1 },
+            to: PatchAddress { universe: to.0.to_string(), address: to.1 },
+            count,
+            invert: false,
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn moves_patched_channels_by_dmx_address() {
+        let table = PatchTable::new(&[entry(("dmx1", 18), ("dmx2", 34), 2)]);
+        let physical = table.apply("dmx1", vec![(16, 1), (17, 2), (18, 3), (19, 4)]);
+
+        assert_eq!(physical["dmx1"], vec![(16, 1), (19, 4)]);
+        assert_eq!(physical["dmx2"], vec![(33, 2), (34, 3)]);
+    }
+
+    #[test]
+    fn applies_invert_and_offset() {
+        let table = PatchTable::new(&[
+            PatchEntry { invert: true, ..entry(("dmx1", 1), ("dmx1", 1), 1) },
+            PatchEntry { offset: 100, ..entry(("dmx1", 2), ("dmx1", 2), 1) },
+        ]);
+        let physical = table.apply("dmx1", vec![(0, 55), (1, 200)]);
+
+        assert_eq!(physical["dmx1"], vec![(0, 200), (1, 255)]);
+    }
+
+    #[test]
+    fn reports_patch_problems() {
+        let universes = ["dmx1", "dmx2"];
+        assert!(problems(&[entry(("dmx1", 18), ("dmx2", 34), 2), entry(("dmx2", 34), ("dmx1", 18), 2)], &universes).is_empty());
+
+        assert_eq!(problems(&[entry(("dmx1", 0), ("dmx2", 1), 1)], &universes).len(), 1);
+        assert_eq!(problems(&[entry(("dmx1", 1), ("dmx2", 500), 20)], &universes).len(), 1);
+        assert_eq!(problems(&[entry(("dmx1", 1), ("dmx3", 1), 1)], &universes).len(), 1);
+
+        // Two logical channels overlapping on dmx2:11-12
+        let overlapping = problems(&[entry(("dmx1", 1), ("dmx2", 10), 3), entry(("dmx1", 101), ("dmx2", 11), 2)], &universes);
+        assert_eq!(overlapping, vec!["Patch dmx1:101 -> dmx2:11: dmx2:11-12 are also the target of the entry from dmx1:1"]);
+    }
+
+    #[test]
+    fn leaves_out_slots_outside_the_universe() {
+        assert_eq!(entry(("dmx1", 511), ("dmx1", 1), 4).slots().collect::<Vec<_>>(), vec![(510, 0), (511, 1)]);
+        assert_eq!(entry(("dmx1", 0), ("dmx1", 1), 1).slots().count(), 0);
+        assert_eq!(entry(("dmx1", 1), ("dmx1", u16::MAX), u16::MAX).slots().count(), 0);
+    }
+}